@@ -1,186 +1,333 @@
+use std::borrow::Cow;
 use std::cell::Cell;
-use std::collections::HashMap;
-use std::iter::repeat;
-use std::sync::atomic::{AtomicU16, Ordering::Relaxed};
+use std::collections::{BTreeMap, HashMap};
+use std::iter::repeat_n;
 
+use failure::Error;
 use maplit::{hashmap, convert_args};
 use rand::{Rng, SeedableRng};
 use rand::prelude::{SmallRng, SliceRandom};
+use tracing::{debug, trace, warn};
 
-use crate::goods::{Good, Task};
-use crate::goods::Good::{Food, Grain};
+use crate::goods::{Good, Goods, Task, DEFAULT_HOURS};
+use crate::ledger::Ledger;
 use crate::market::{Market, GoodMap};
+use crate::brain::Strategy;
+use crate::region::RegionId;
 use std::cmp::Reverse;
 use crate::record::add;
+use crate::recipe::RecipeGraph;
+use crate::units::{Money, Quantity};
+use crate::utility::Preferences;
 
 pub type AgentId = u16;
 
+/// Agents keyed by id, ordered so iteration is the same on every run
+pub type Agents = BTreeMap<AgentId, Agent>;
+
 #[derive(Clone, PartialEq, Debug, Serialize)]
 pub struct Agent {
     pub id: AgentId,
-    pub cash: i16,
-    pub res: HashMap<Good, i16>,
+    pub cash: Money,
+    pub res: HashMap<Good, Quantity>,
     pub skill: HashMap<Good, f32>,
+    /// region whose market the agent trades in
+    pub home: RegionId,
+    /// decision rules for trading, producing and eating
+    pub strategy: Strategy,
+    /// uses of each tool since a unit of it last wore out
+    pub wear: HashMap<Good, u16>,
+    /// hours of work each tick, for itself or for wages
+    pub hours: u16,
+    /// hours not yet worked this tick
+    pub hours_left: u16,
+    /// 1 when well fed, falls while it goes hungry and it dies at 0
+    pub health: f32,
+    /// what it eats and how much it enjoys it
+    pub preferences: Preferences,
+    /// fraction of a unit of each good gone bad but not yet lost
+    pub rot: HashMap<Good, f64>,
 }
 
-// track last used id
-static ID: AtomicU16 = AtomicU16::new(0);
+/// Hunger that builds up over ticks: health falls by `loss` for each unit eaten short of
+/// `need`, and recovers by `recovery` each tick the agent eats its fill
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Health {
+    pub need: Quantity,
+    pub loss: f32,
+    pub recovery: f32,
+}
 
-pub fn new_agent_id() -> u16 {
-    ID.fetch_add(1, Relaxed)
+impl Health {
+    /// Health after a tick starting at `health` and eating `eaten`, between 0 and 1
+    pub fn after(&self, health: f32, eaten: Quantity) -> f32 {
+        let short = (self.need - eaten).max(Quantity::ZERO);
+        let health = if short > Quantity::ZERO {
+            health - self.loss * short.0 as f32
+        } else {
+            health + self.recovery
+        };
+        // so rounding doesn't leave it alive after losing all its health
+        if health < 1e-6 { 0. } else { health.min(1.) }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct MU(pub Vec<(i16, u8)>);
+pub struct MU(pub Vec<(Money, u8)>);
 
 
 impl Agent {
-    pub fn choose_trade(&self, price: i16, mu: &MU, good: Good) -> i16 {
-        let p = price;
+    pub fn choose_trade(&self, price: Money, mu: &MU, good: Good) -> Quantity {
         let supply = self.res[&good];
+        let to_trade = self.demand(price, mu, good);
+        trace!(%good, %price, %supply, %to_trade, "chose trade");
+        add("trades", (good, price, supply, to_trade, self.id));
+        to_trade
+    }
 
-//        dbg!(p, supply);
+    /// Units the agent wants to buy (positive) or sell (negative) at `price`, without recording
+    pub fn demand(&self, price: Money, mu: &MU, good: Good) -> Quantity {
+        self.strategy.brain().demand(self, price, &self.values(mu, good), good)
+    }
 
-        // find min to_trade s.t. the marginal utility of buying one more is less than the price
-        let mut to_trade = 0;
-        while mu.mu_buy(supply + to_trade) > p {
-            to_trade += 1;
-        }
-        // find max to_trade s.t. the marginal utility of selling one more is greater than the price
-        while mu.mu_sell(supply + to_trade) < p && to_trade + supply >= 0 {
-            to_trade -= 1;
+    /// Limit prices for each unit the agent would buy and each unit it would sell, best first
+    pub fn reservation_prices(&self, mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        self.strategy.brain().reservation_prices(self, &self.values(mu, good), good)
+    }
+
+    /// Marginal utility of `good` to the agent: by its own preferences if it eats it, `mu` otherwise
+    pub fn values<'m>(&self, mu: &'m MU, good: Good) -> Cow<'m, MU> {
+        if self.preferences.eats(good) {
+            Cow::Owned(self.preferences.mu(good, &self.res))
+        } else {
+            Cow::Borrowed(mu)
         }
-        add("trades", (good, price, supply, to_trade, self.id));
-        to_trade
     }
 
-    pub fn choose_task(&self, tasks: &'a [Task], market: &dyn Market) -> &'a Task {
-        tasks.iter()
-            .max_by_key(|&task| {
-                let (val, rev, cost) = task.value(market, self.skill[&task.output.0]);
-                let have_inputs = task.inputs.iter()
-                    .all(|(g, amt)| {
-                        if self.res[g] >= *amt {
-                            true
-                        } else {
-                            dbg!(self.res[g], amt, g);
-                            false
-                        }
-                    });
-
-                add("tasks", (&task.name, (val, rev, cost), -1));
-                if have_inputs {
-                    val as i32
-                } else {
-                    println!("excluding task due to insufficient resources: {}, v: {:?}", &task.name, (val, rev, cost));
-                    0
-                }
+    /// Task to perform this tick, none if the agent doesn't produce
+    pub fn choose_task<'a>(&self, tasks: &'a [Task], market: &dyn Market) -> Option<&'a Task> {
+        self.strategy.brain().choose_task(self, tasks, market)
+    }
+
+    /// Units of `food` the agent eats this tick, none if it doesn't eat
+    pub fn eats(&self, food: Good, mu: &MU, max: Quantity) -> Option<Quantity> {
+        self.strategy.brain().consume(self, food, mu, max)
+    }
+
+    /// Units of each good it eats this tick by its preferences, never more than it has.
+    /// Each good is valued with the others at what the agent holds.
+    /// None if it doesn't eat or has no taste for anything.
+    pub fn diet(&self) -> Option<Vec<(Good, Quantity)>> {
+        let p = &self.preferences;
+        let goods = p.utility.goods();
+        if goods.is_empty() {
+            return None;
+        }
+        goods.into_iter()
+            .map(|good| {
+                let eaten = self.eats(good, &p.mu(good, &self.res), p.max)?;
+                Some((good, eaten.min(self.res[&good]).max(Quantity::ZERO)))
             })
-            .expect("If tasks non-empty, then should have best task")
+            .collect()
     }
 
-    pub fn perform_task(&mut self, task: &Task, market: &mut dyn Market) {
-        for &(good, amt) in &task.inputs {
-            let owned: i16 = self.res[&good];
+    /// Units held of the goods it eats
+    pub fn food(&self) -> Quantity {
+        self.preferences.utility.goods().iter().map(|g| self.res[g]).sum()
+    }
+
+    /// Skill at making `good`, worse the less healthy the agent is
+    pub fn productivity(&self, good: Good) -> f32 {
+        self.skill[&good] * self.health
+    }
+
+    /// Buy whatever agent `id` lacks of the inputs and tools of `task` from the market, right away
+    pub fn buy_inputs(agents: &mut Agents, id: AgentId, task: &Task, market: &mut dyn Market, ledger: &mut Ledger) {
+        for (good, amt) in task.needs() {
+            let owned = agents[&id].res[&good];
             if owned < amt {
-                market.buy(self, good, (owned - amt).abs())
-                    .unwrap();
+                let missing = amt - owned;
+                match market.buy(agents, id, good, missing, ledger) {
+                    Ok(fill) => debug!(%good, %missing, bought = %fill.qty, cost = %fill.value, "bought inputs"),
+                    Err(e) => warn!(%good, "buying inputs failed: {}", e),
+                }
             }
+        }
+    }
+
+    /// Turn the task's inputs into its outputs at the agent's productivity, wearing its tools. If
+    /// the agent doesn't hold all the inputs and tools nothing happens and false is returned,
+    /// stocks never go negative.
+    pub fn perform_task(&mut self, task: &Task, ledger: &mut Ledger) -> bool {
+        let skill = self.productivity(task.output.0);
+        self.perform_task_with(task, skill, ledger)
+    }
+
+    /// `perform_task` with the agent's goods, worked by someone with `skill`
+    pub fn perform_task_with(&mut self, task: &Task, skill: f32, ledger: &mut Ledger) -> bool {
+        if let Some((good, amt)) = task.needs().find(|&(g, amt)| self.res[&g] < amt) {
+            debug!(task = %task.name, %good, have = %self.res[&good], need = %amt, "missing inputs, task skipped");
+            return false;
+        }
+        for &(good, amt) in &task.inputs {
             *self.res.get_mut(&good).unwrap() -= amt;
+            ledger.consume(self.id, good, amt);
+        }
+        for tool in &task.tools {
+            let uses = self.wear.entry(tool.good).or_insert(0);
+            *uses += 1;
+            if *uses >= tool.life {
+                *uses = 0;
+                *self.res.get_mut(&tool.good).unwrap() -= Quantity(1);
+                ledger.consume(self.id, tool.good, Quantity(1));
+                debug!(task = %task.name, good = %tool.good, "tool worn out");
+            }
+        }
+        for (good, made) in task.outputs(skill) {
+            let before = self.res[&good];
+            *self.res.get_mut(&good).unwrap() += made;
+            ledger.produce(self.id, good, made);
+            debug!(task = %task.name, %good, %before, after = %self.res[&good], "performed task");
+        }
+        true
+    }
+
+    /// Lose a `decay` fraction of the `good` held, a whole unit at a time, fractions carry over
+    /// to the next tick. Returns the units lost.
+    pub fn spoil(&mut self, good: Good, decay: f64) -> Quantity {
+        let held = self.res[&good];
+        if held <= Quantity::ZERO {
+            self.rot.remove(&good);
+            return Quantity::ZERO;
         }
-        let &(good, amt) = &task.output;
-        println!("Good before: {:?}, {:?}", good, self.res[&good]);
-        *self.res.get_mut(&good).unwrap() += (amt as f32 * self.skill[&good]).round() as i16;
-        println!("after: {:?}", self.res[&good]);
+        let rot = self.rot.entry(good).or_insert(0.);
+        *rot += held.0 as f64 * decay;
+        let lost = Quantity((rot.floor() as i32).min(held.0));
+        *rot -= lost.0 as f64;
+        *self.res.get_mut(&good).unwrap() -= lost;
+        lost
     }
 
-    pub fn pre_made(num: usize) -> HashMap<AgentId, Agent> {
-        let mut agents = HashMap::with_capacity(num);
-        let mut rng = SmallRng::from_entropy();
+    /// `num` agents holding Grain and Food, with two different skills at them.
+    /// Any other goods start at nothing with skill 1.
+    pub fn pre_made(num: usize, goods: &Goods, rng: &mut impl Rng) -> Agents {
+        let grain = goods.get("Grain").expect("pre-made agents need Grain");
+        let food = goods.get("Food").expect("pre-made agents need Food");
+        let mut agents = Agents::new();
         for _i in 0..num {
-            let f: Vec<&f32> = [0.1, 1.0, 1.0, 2.0].choose_multiple(&mut rng, 2).collect::<Vec<&f32>>();
-            Agent::new_into_map(&mut agents,
-                                rng.gen_range(100, 500),
-                                hashmap! {Grain => rng.gen_range(5, 90), Food => rng.gen_range(2, 15)},
-                                hashmap! {Grain => *f[0], Food => *f[1]},
-            );
+            let f: Vec<&f32> = [0.1, 1.0, 1.0, 2.0].choose_multiple(rng, 2).collect();
+            let cash = Money(rng.gen_range(100, 500));
+            let mut res: HashMap<_, _> = goods.all().iter().map(|&g| (g, Quantity::ZERO)).collect();
+            let mut skill: HashMap<_, _> = goods.all().iter().map(|&g| (g, 1.0)).collect();
+            res.insert(grain, Quantity(rng.gen_range(5, 90)));
+            res.insert(food, Quantity(rng.gen_range(2, 15)));
+            skill.insert(grain, *f[0]);
+            skill.insert(food, *f[1]);
+            Agent::new_into_map(&mut agents, cash, res, skill).expect("out of agent ids");
         }
         agents
     }
 
-    pub fn new(cash: i16, res: HashMap<Good, i16>, skill: HashMap<Good, f32>) -> Agent {
-        Agent { id: new_agent_id(), cash, res, skill }
+    /// The id after the largest id in `map`, an error if that's the largest id there is
+    pub fn next_id(map: &Agents) -> Result<AgentId, Error> {
+        match map.keys().next_back() {
+            None => Ok(0),
+            Some(&id) => match id.checked_add(1) {
+                Some(next) => Ok(next),
+                None => bail!("no agent ids left after {}", id),
+            },
+        }
     }
 
-    pub fn new_into_map(map: &mut HashMap<u16, Agent>,
-                        cash: i16,
-                        res: HashMap<Good, i16>,
-                        skill: HashMap<Good, f32>) {
-        let id = new_agent_id();
-        map.insert(id, Agent { id, cash, res, skill });
+    /// Insert a new agent with the next id after the largest id in `map`,
+    /// so agent ids only depend on the map being built. Returns the new id.
+    pub fn new_into_map(map: &mut Agents,
+                        cash: Money,
+                        res: HashMap<Good, Quantity>,
+                        skill: HashMap<Good, f32>) -> Result<AgentId, Error> {
+        let id = Agent::next_id(map)?;
+        map.insert(id, Agent::new_with_id(id, cash, res, skill));
+        Ok(id)
     }
 
-    pub fn new_with_id(id: u16, cash: i16, res: HashMap<Good, i16>, skill: HashMap<Good, f32>) -> Agent {
-        Agent { id, cash, res, skill }
+    pub fn new_with_id(id: u16, cash: Money, res: HashMap<Good, Quantity>, skill: HashMap<Good, f32>) -> Agent {
+        Agent {
+            id,
+            cash,
+            res,
+            skill,
+            home: 0,
+            strategy: Strategy::default(),
+            wear: HashMap::new(),
+            hours: DEFAULT_HOURS,
+            hours_left: DEFAULT_HOURS,
+            health: 1.,
+            preferences: Preferences::default(),
+            rot: HashMap::new(),
+        }
     }
 }
 
 impl MU {
-    pub fn from_utility(u: &[i16], discount: f64) -> MU {
+    pub fn from_utility(u: &[Money], discount: f64) -> MU {
         let mut mu = Vec::with_capacity(u.len() - 1);
         let d2 = discount * discount;
         for i in 0..(u.len() - 1) {
             let cur = u[i + 1] - u[i];
             mu.push((cur, 0));
-            mu.push(((cur as f64 * discount).round() as i16, 1));
-            mu.push(((cur as f64 * d2).round() as i16, 2));
-            mu.push(((cur as f64 * d2 * discount).round() as i16, 3));
-            mu.push(((cur as f64 * d2 * d2).round() as i16, 4));
+            mu.push((cur.scale(discount), 1));
+            mu.push((cur.scale(d2), 2));
+            mu.push((cur.scale(d2 * discount), 3));
+            mu.push((cur.scale(d2 * d2), 4));
         }
-        mu.sort_by_key(|&x| Reverse(x.clone()));
+        mu.sort_by_key(|&x| Reverse(x));
         MU(mu)
     }
 
-    pub fn from_curr_mu(curr_mu: &[i16], discount: f64) -> MU {
+    pub fn from_curr_mu(curr_mu: &[Money], discount: f64) -> MU {
         let mut mu = Vec::with_capacity(curr_mu.len() * 2);
         let d2 = discount * discount;
-        for i in 0..curr_mu.len() {
-            mu.push((curr_mu[i], 0));
-            mu.push(((curr_mu[i] as f64 * discount).round() as i16, 1));
-            mu.push(((curr_mu[i] as f64 * d2).round() as i16, 2));
-            mu.push(((curr_mu[i] as f64 * d2 * discount).round() as i16, 3));
-            mu.push(((curr_mu[i] as f64 * d2 * d2).round() as i16, 4));
+        for &cur in curr_mu {
+            mu.push((cur, 0));
+            mu.push((cur.scale(discount), 1));
+            mu.push((cur.scale(d2), 2));
+            mu.push((cur.scale(d2 * discount), 3));
+            mu.push((cur.scale(d2 * d2), 4));
         }
-        mu.sort_by_key(|&x| Reverse(x.clone()));
+        mu.sort_by_key(|&x| Reverse(x));
         MU(mu)
     }
 
+    /// Marginal utility of `good` from what it makes in its best use, through any number of
+    /// tasks. Nothing is worth keeping a good no task needs for.
     pub fn from_market(market: &dyn Market, tasks: &[Task], good: Good) -> MU {
-        let (mu, &input) = tasks.iter()
-            .filter(|&t| t.inputs
-                .iter()
-                .any(|(g, _)| *g == good))
-            .map(|task| {
-                let (good_, input) = task.inputs
-                    .iter()
-                    .filter(|(g, _)| *g == good).take(1).next().unwrap();
-                assert!(good == *good_);
-                let (out_g, output) = task.output;
-                let out_value = market.value(out_g, output);
-                let mu_prime = out_value / input;
-                (mu_prime, input)
-            }).max_by_key(|(mu, _)| *mu).unwrap();
-//        dbg!(mu, input);
+        let (mu, input) = match RecipeGraph::new(tasks).best_use(good, market) {
+            Some((mu, input)) => (mu.max(Money::ZERO), input),
+            None => return MU(vec![(Money::ZERO, 0)]),
+        };
 
         MU((0..3)
             .flat_map(|i| {
-                repeat((((mu as f64 * 0.8_f64.powf(i as f64)) as i16), i))
-                    .take(input as usize)
+                repeat_n((Money((mu.0 as f64 * 0.8_f64.powf(i as f64)) as i64), i), input.0.max(0) as usize)
             })
             .collect())
     }
 
-    pub fn utility(&self, u_0: i16) -> Vec<i16> {
+    /// Units kept for later, of a good that loses a `decay` fraction every tick and costs
+    /// `storage` a unit to keep, worth less by what is expected to spoil and the storage until then
+    pub fn kept(&self, decay: f64, storage: Money) -> MU {
+        let mut mu: Vec<_> = self.0.iter()
+            .map(|&(m, i)| {
+                let left = m.scale((1. - decay).powi(i as i32));
+                (left.saturating_sub(storage.saturating_mul(Quantity(i as i32))).max(Money::ZERO), i)
+            })
+            .collect();
+        // stable, so units worth the same stay in the order they were
+        mu.sort_by_key(|&(m, _)| Reverse(m));
+        MU(mu)
+    }
+
+    pub fn utility(&self, u_0: Money) -> Vec<Money> {
         let mut util = Vec::with_capacity(self.0.len() + 2);
         util.push(u_0);
         for (i, &(d, _)) in self.0.iter().enumerate() {
@@ -189,9 +336,10 @@ impl MU {
         util
     }
 
-    pub fn mu_consume(&self, supply: i16) -> i16 {
+    pub fn mu_consume(&self, supply: Quantity) -> Quantity {
         let mut to_consume = 0;
         let mut to_save = 0;
+        let supply = supply.0;
         for (_d, i) in &self.0 {
 //            dbg!(to_consume, to_save, _d, i);
             if to_save + to_consume >= supply {
@@ -202,23 +350,21 @@ impl MU {
                 to_consume += 1;
             }
         }
-        dbg!(to_consume);
-        return to_consume;
+        trace!(supply, to_consume, "consume");
+        Quantity(to_consume)
     }
 
-    fn mu_buy(&self, supply: i16) -> i16 {
-        if supply as usize > self.0.len() - 1 {
-            0
-        } else {
-            self.0[supply as usize].0
-        }
+    /// Worth of the next unit on top of `supply`, nothing past the end of the schedule
+    pub(crate) fn mu_buy(&self, supply: Quantity) -> Money {
+        self.0.get(supply.0 as usize).map_or(Money::ZERO, |&(m, _)| m)
     }
 
-    fn mu_sell(&self, supply: i16) -> i16 {
-        if supply as usize > self.0.len() - 1 || supply == 0 {
-            0
+    /// Worth of the last of `supply` units, nothing past the end of the schedule
+    pub(crate) fn mu_sell(&self, supply: Quantity) -> Money {
+        if supply.0 <= 0 || supply.0 as usize >= self.0.len() {
+            Money::ZERO
         } else {
-            self.0[supply as usize - 1].0
+            self.0[supply.0 as usize - 1].0
         }
     }
 }
@@ -229,34 +375,53 @@ mod tests {
 
     use failure::Error;
 
+    use crate::goods::Tool;
     use crate::market::UnexecutedTrades;
+    use crate::testing::goods;
 
     use super::*;
 
+    fn food_and_grain() -> (Good, Good) {
+        let (_, [food, grain]) = goods(["Food", "Grain"]);
+        (food, grain)
+    }
+
+    #[test]
+    fn ids_run_out() {
+        let mut agents = Agents::new();
+        assert_eq!(Agent::new_into_map(&mut agents, Money(0), HashMap::new(), HashMap::new()).unwrap(), 0);
+        agents.insert(AgentId::MAX, Agent::new_with_id(AgentId::MAX, Money(0), HashMap::new(), HashMap::new()));
+        assert!(Agent::next_id(&agents).is_err());
+        assert!(Agent::new_into_map(&mut agents, Money(0), HashMap::new(), HashMap::new()).is_err());
+    }
+
     #[test]
     fn test_from_market() {
+        let (food, grain) = food_and_grain();
         let tasks = vec![
-            Task::new("Bake", &[(Grain, 30)], (Food, 10)),
-            Task::new("Farm", &[], (Grain, 10)),
+            Task::new("Bake", &[(grain, Quantity(30))], (food, Quantity(10))),
+            Task::new("Farm", &[], (grain, Quantity(10))),
         ];
-        let market = MockMarket(20);
-        let mu = MU::from_market(&market, &tasks, Grain);
+        let market = MockMarket(Money(20));
+        let mu = MU::from_market(&market, &tasks, grain);
 
-        assert_eq!(mu.mu_buy(1), 6);
-        assert_eq!(mu.mu_buy(35), 4);
-        assert_eq!(mu.mu_buy(1), mu.mu_buy(28));
+        assert_eq!(mu.mu_buy(Quantity(1)), Money(6));
+        assert_eq!(mu.mu_buy(Quantity(35)), Money(4));
+        assert_eq!(mu.mu_buy(Quantity(1)), mu.mu_buy(Quantity(28)));
     }
 
     #[test]
     fn test_mu() {
         let mu = make_mu();
 
-        assert_eq!(mu.mu_buy(2), 10);
-        assert_eq!(mu.mu_sell(2), 12);
+        assert_eq!(mu.mu_buy(Quantity(2)), Money(10));
+        assert_eq!(mu.mu_sell(Quantity(2)), Money(12));
+        // an empty schedule is worth nothing rather than out of bounds
+        assert_eq!((MU(vec![]).mu_buy(Quantity(0)), MU(vec![]).mu_sell(Quantity(1))), (Money::ZERO, Money::ZERO));
 
         //dbg!(&mu);
-        assert_eq!(mu.mu_consume(3), 3);
-        assert_eq!(mu.mu_consume(10), 4);
+        assert_eq!(mu.mu_consume(Quantity(3)), Quantity(3));
+        assert_eq!(mu.mu_consume(Quantity(10)), Quantity(4));
     }
 
     #[test]
@@ -269,37 +434,121 @@ mod tests {
         assert_eq!(choose_trade_builder(11, 0), 2);
     }
 
+    #[test]
+    fn perform_needs_inputs() {
+        let (food, grain) = food_and_grain();
+        let bake = Task::new("Bake", &[(grain, Quantity(30))], (food, Quantity(10)));
+        let mut a = Agent::new_with_id(0, Money(20), hashmap! {grain => Quantity(20), food => Quantity(0)},
+                               hashmap! {grain => 1.0, food => 2.0});
+        let mut ledger = Ledger::default();
+
+        assert!(!a.perform_task(&bake, &mut ledger));
+        assert_eq!(a.res[&grain], Quantity(20));
+        assert!(ledger.is_empty());
+
+        a.res.insert(grain, Quantity(30));
+        assert!(a.perform_task(&bake, &mut ledger));
+        assert_eq!(a.res[&grain], Quantity(0));
+        assert_eq!(a.res[&food], Quantity(20));
+        assert_eq!(ledger.len(), 2);
+    }
+
+    #[test]
+    fn tools_wear_out() {
+        let (_, [flour, grain, straw, stone]) = goods(["Flour", "Grain", "Straw", "Millstone"]);
+        let mill = Task::new("Mill", &[(grain, Quantity(10))], (flour, Quantity(5)))
+            .with_byproducts(&[(straw, Quantity(2))])
+            .with_tools(&[Tool { good: stone, qty: Quantity(1), life: 2 }]);
+        let res = hashmap! {flour => Quantity(0), grain => Quantity(30), straw => Quantity(0), stone => Quantity(0)};
+        let mut a = Agent::new_with_id(0, Money(20), res, hashmap! {flour => 2.0, grain => 1.0, straw => 1.0, stone => 1.0});
+        let mut ledger = Ledger::default();
+
+        // no millstone
+        assert!(!a.perform_task(&mill, &mut ledger));
+        a.res.insert(stone, Quantity(1));
+        assert!(a.perform_task(&mill, &mut ledger));
+        assert_eq!((a.res[&flour], a.res[&straw], a.res[&stone]), (Quantity(10), Quantity(4), Quantity(1)));
+        // second use wears it out
+        assert!(a.perform_task(&mill, &mut ledger));
+        assert_eq!((a.res[&grain], a.res[&stone]), (Quantity(10), Quantity(0)));
+        assert!(!a.perform_task(&mill, &mut ledger));
+    }
+
+    #[test]
+    fn goods_spoil() {
+        let (food, grain) = food_and_grain();
+        let mut a = Agent::new_with_id(0, Money(20), hashmap! {food => Quantity(10), grain => Quantity(0)},
+                               hashmap! {food => 1.0, grain => 1.0});
+
+        // 1.5 units go bad, the half carries over to the next tick
+        assert_eq!(a.spoil(food, 0.15), Quantity(1));
+        assert_eq!(a.spoil(food, 0.15), Quantity(1));
+        assert_eq!(a.res[&food], Quantity(8));
+        assert_eq!(a.spoil(grain, 0.15), Quantity::ZERO);
+
+        // a unit kept a tick is worth half, less 2 of storage
+        let mu = MU(vec![(Money(40), 0), (Money(30), 1), (Money(20), 0)]);
+        assert_eq!(mu.kept(0.5, Money(2)).0, vec![(Money(40), 0), (Money(20), 0), (Money(13), 1)]);
+        assert_eq!(mu.kept(0., Money::ZERO).0, vec![(Money(40), 0), (Money(30), 1), (Money(20), 0)]);
+    }
+
+    #[test]
+    fn hunger_builds_up() {
+        let (food, grain) = food_and_grain();
+        let bake = Task::new("Bake", &[(grain, Quantity(10))], (food, Quantity(10)));
+        let mut a = Agent::new_with_id(0, Money(20), hashmap! {food => Quantity(0), grain => Quantity(20)},
+                               hashmap! {food => 1.0, grain => 1.0});
+        let health = Health { need: Quantity(3), loss: 0.25, recovery: 0.5 };
+
+        // two units short, then one
+        a.health = health.after(a.health, Quantity(1));
+        assert_eq!(a.health, 0.5);
+        assert_eq!(health.after(a.health, Quantity(2)), 0.25);
+        // works at half its skill
+        assert!(a.perform_task(&bake, &mut Ledger::default()));
+        assert_eq!(a.res[&food], Quantity(5));
+        // eating its fill recovers, never past full health; starving kills
+        assert_eq!(health.after(0.75, Quantity(4)), 1.0);
+        assert_eq!(health.after(a.health, Quantity::ZERO), 0.0);
+    }
+
     fn make_mu() -> MU {
-        let utility = [20_i16, 35, 47, 57, 62];
-        MU::from_utility(&utility, 0.4)
+        let utility = [20, 35, 47, 57, 62];
+        MU::from_utility(&utility.iter().map(|&u| Money(u)).collect::<Vec<_>>(), 0.4)
     }
 
-    fn choose_trade_builder(p: i16, s: i16) -> i16 {
+    fn choose_trade_builder(p: i64, s: i32) -> i32 {
+        let (food, grain) = food_and_grain();
         let mu = make_mu();
-        let a = Agent::new(20, hashmap! {Grain => s, Food => 40});
-        a.choose_trade(p, &mu, Grain)
+        let a = Agent::new_with_id(0, Money(20), hashmap! {grain => Quantity(s), food => Quantity(40)},
+                           hashmap! {grain => 1.0, food => 1.0});
+        a.choose_trade(Money(p), &mu, grain).0
     }
 
-    struct MockMarket(pub i16);
+    struct MockMarket(pub Money);
 
     impl Market for MockMarket {
-        fn price(&self, _good: Good) -> i16 {
+        fn goods(&self) -> Vec<Good> {
+            unimplemented!()
+        }
+
+        fn price(&self, _good: Good) -> Money {
             self.0
         }
 
-        fn old_price(&self, _good: Good) -> i16 {
+        fn old_price(&self, _good: Good) -> Money {
             unimplemented!()
         }
 
-        fn trade(&mut self, _cash_and_id: (i16, u16), _good: Good, _amt: i16) -> Result<(), Error> {
+        fn trade(&mut self, _cash_and_id: (Money, AgentId), _good: Good, _amt: Quantity) -> Result<(), Error> {
             unimplemented!()
         }
 
-        fn execute_trade(&mut self, _agents: &mut HashMap<u16, Agent, RandomState>, _good: Good) -> UnexecutedTrades {
+        fn execute_trade(&mut self, _agents: &mut Agents, _good: Good, _ledger: &mut Ledger) -> UnexecutedTrades {
             unimplemented!()
         }
 
-        fn update_price(&mut self, _ts: UnexecutedTrades, _good: Good) -> i16 {
+        fn update_price(&mut self, _ts: UnexecutedTrades, _good: Good) -> Money {
             unimplemented!()
         }
     }
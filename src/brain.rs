@@ -0,0 +1,263 @@
+use tracing::{debug, trace, warn};
+
+use crate::agent::{Agent, MU};
+use crate::firm::Firm;
+use crate::goods::{Good, Task};
+use crate::learner::Learner;
+use crate::market::Market;
+use crate::merchant::Merchant;
+use crate::record::add;
+use crate::trader::Trader;
+use crate::units::{Money, Quantity};
+
+/// Decision rules of an agent: what to trade, what to produce and how much to eat.
+/// The agent's state is passed in, the brain only holds what its rules need to remember.
+pub trait Brain {
+    /// Units the agent wants to buy (positive) or sell (negative) at a posted `price`
+    fn demand(&self, a: &Agent, price: Money, mu: &MU, good: Good) -> Quantity;
+
+    /// Limit prices for each unit the agent would buy and each unit it would sell, best first
+    fn reservation_prices(&self, a: &Agent, mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>);
+
+    /// Next task to perform this tick, among those that fit in the agent's hours left.
+    /// None to stop working.
+    fn choose_task<'a>(&self, a: &Agent, tasks: &'a [Task], market: &dyn Market) -> Option<&'a Task>;
+
+    /// Units of `food` to eat this tick valuing it by `mu`, at most `max`.
+    /// None for agents that don't eat, and so can't starve.
+    fn consume(&self, a: &Agent, food: Good, mu: &MU, max: Quantity) -> Option<Quantity>;
+
+    /// Whether to hire out its hours left at `wage` an hour rather than perform `task`.
+    /// By default when the task makes less than the wages for its hours.
+    fn prefers_wage(&self, a: &Agent, task: &Task, market: &dyn Market, wage: Money) -> bool {
+        task.value(market, a.productivity(task.output.0))
+            .is_ok_and(|(profit, _, _)| profit < wage.saturating_mul(Quantity(task.labor as i32)))
+    }
+
+    /// Whether it would pay others to perform `task` with its goods
+    fn employs(&self, _task: &Task) -> bool {
+        true
+    }
+
+    /// Called with the price of every good in the agent's market after each trade round
+    fn observe(&mut self, _good: Good, _price: Money) {}
+
+    /// Called after the agent worked on `task`, with the profit it made at market prices.
+    /// Zero if it lacked the inputs.
+    fn learn(&mut self, _task: &Task, _profit: Money) {}
+}
+
+/// The default agent: values goods by their marginal utility, picks the most valuable task it
+/// has the inputs for and eats until the next unit is worth less than keeping it
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize)]
+pub struct Household;
+
+/// Always performs the same task, however little it pays, otherwise a `Household`
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Specialist {
+    pub task: String,
+}
+
+/// An agent's decision rules. Kept as an enum so agents stay comparable and serializable,
+/// each variant's rules are in its `Brain` impl.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub enum Strategy {
+    Household(Household),
+    Specialist(Specialist),
+    Learner(Learner),
+    Merchant(Merchant),
+    Trader(Trader),
+    Firm(Firm),
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Household(Household)
+    }
+}
+
+impl Strategy {
+    pub fn brain(&self) -> &dyn Brain {
+        match self {
+            Strategy::Household(b) => b,
+            Strategy::Specialist(b) => b,
+            Strategy::Learner(b) => b,
+            Strategy::Merchant(b) => b,
+            Strategy::Trader(b) => b,
+            Strategy::Firm(b) => b,
+        }
+    }
+
+    pub fn brain_mut(&mut self) -> &mut dyn Brain {
+        match self {
+            Strategy::Household(b) => b,
+            Strategy::Specialist(b) => b,
+            Strategy::Learner(b) => b,
+            Strategy::Merchant(b) => b,
+            Strategy::Trader(b) => b,
+            Strategy::Firm(b) => b,
+        }
+    }
+
+    pub fn merchant(&self) -> Option<&Merchant> {
+        match self {
+            Strategy::Merchant(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    pub fn trader(&self) -> Option<&Trader> {
+        match self {
+            Strategy::Trader(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn trader_mut(&mut self) -> Option<&mut Trader> {
+        match self {
+            Strategy::Trader(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn firm(&self) -> Option<&Firm> {
+        match self {
+            Strategy::Firm(f) => Some(f),
+            _ => None,
+        }
+    }
+}
+
+impl Brain for Household {
+    fn demand(&self, a: &Agent, price: Money, mu: &MU, good: Good) -> Quantity {
+        let supply = a.res[&good];
+        let one = Quantity(1);
+
+        // find min to_trade s.t. the marginal utility of buying one more is less than the price
+        let mut to_trade = Quantity::ZERO;
+        while mu.mu_buy(supply + to_trade) > price {
+            to_trade += one;
+        }
+        // find max to_trade s.t. the marginal utility of selling one more is greater than the price
+        while mu.mu_sell(supply + to_trade) < price && to_trade + supply >= Quantity::ZERO {
+            to_trade -= one;
+        }
+        to_trade
+    }
+
+    /// Bids are the marginal utility of one more unit, as long as the agent can pay them,
+    /// asks the utility given up by selling one more
+    fn reservation_prices(&self, a: &Agent, mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        let supply = a.res[&good];
+        let mut bids = Vec::new();
+        let mut budget = a.cash;
+        loop {
+            let p = mu.mu_buy(supply + Quantity(bids.len() as i32));
+            if p <= Money::ZERO || p > budget {
+                break;
+            }
+            budget -= p;
+            bids.push(p);
+        }
+        // never give a unit away for nothing
+        let asks = (0..supply.0.max(0))
+            .map(|k| mu.mu_sell(supply - Quantity(k)).max(Money(1)))
+            .collect();
+        (bids, asks)
+    }
+
+    fn choose_task<'a>(&self, a: &Agent, tasks: &'a [Task], market: &dyn Market) -> Option<&'a Task> {
+        tasks.iter()
+            .filter(|t| t.labor <= a.hours_left)
+            .max_by_key(|&task| {
+                let (val, rev, cost) = match task.value(market, a.productivity(task.output.0)) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!(task = %task.name, "excluding task, can't value it: {}", e);
+                        return Money::ZERO;
+                    }
+                };
+                let have_inputs = task.needs()
+                    .all(|(g, amt)| {
+                        if a.res[&g] >= amt {
+                            true
+                        } else {
+                            trace!(good = %g, have = %a.res[&g], need = %amt, "missing input");
+                            false
+                        }
+                    });
+
+                add("tasks", (&task.name, (val, rev, cost), -1));
+                if have_inputs {
+                    val
+                } else {
+                    debug!(task = %task.name, %val, %rev, %cost, "excluding task due to insufficient resources");
+                    Money::ZERO
+                }
+            })
+    }
+
+    fn consume(&self, a: &Agent, food: Good, mu: &MU, max: Quantity) -> Option<Quantity> {
+        Some(max.min(mu.mu_consume(a.res[&food])))
+    }
+}
+
+impl Brain for Specialist {
+    fn demand(&self, a: &Agent, price: Money, mu: &MU, good: Good) -> Quantity {
+        Household.demand(a, price, mu, good)
+    }
+
+    fn reservation_prices(&self, a: &Agent, mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        Household.reservation_prices(a, mu, good)
+    }
+
+    /// Falls back to choosing like a household if its task doesn't exist
+    fn choose_task<'a>(&self, a: &Agent, tasks: &'a [Task], market: &dyn Market) -> Option<&'a Task> {
+        match tasks.iter().find(|t| t.name == self.task) {
+            Some(task) if task.labor <= a.hours_left => Some(task),
+            Some(_) => None,
+            None => Household.choose_task(a, tasks, market),
+        }
+    }
+
+    fn consume(&self, a: &Agent, food: Good, mu: &MU, max: Quantity) -> Option<Quantity> {
+        Household.consume(a, food, mu, max)
+    }
+
+    fn prefers_wage(&self, _a: &Agent, _task: &Task, _market: &dyn Market, _wage: Money) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::testing::{goods, market};
+
+    use super::*;
+
+    #[test]
+    fn mixed_brains() {
+        let (_, [food, grain]) = goods(["Food", "Grain"]);
+        let tasks = [Task::new("Bake", &[(grain, Quantity(25))], (food, Quantity(10))),
+                     Task::new("Farm", &[], (grain, Quantity(10)))];
+        let market = market(hashmap! {food => Money(25), grain => Money(1)});
+        let mut a = Agent::new_with_id(0, Money(100), hashmap! {food => Quantity(3), grain => Quantity(30)},
+                               hashmap! {food => 1.0, grain => 1.0});
+        let mu = MU::from_curr_mu(&[Money(120), Money(60), Money(50), Money(40)], 0.8);
+
+        // baking pays best
+        assert_eq!(a.choose_task(&tasks, &market).map(|t| &t.name[..]), Some("Bake"));
+        assert_eq!(a.eats(food, &mu, Quantity(5)), Some(Quantity(1)));
+
+        a.strategy = Strategy::Specialist(Specialist { task: "Farm".into() });
+        assert_eq!(a.choose_task(&tasks, &market).map(|t| &t.name[..]), Some("Farm"));
+        assert_eq!(a.eats(food, &mu, Quantity(5)), Some(Quantity(1)));
+
+        a.strategy = Strategy::Trader(Trader::new(Quantity(10), Money(1), 0.1));
+        assert_eq!(a.choose_task(&tasks, &market), None);
+        assert_eq!(a.eats(food, &mu, Quantity(5)), None);
+        assert_eq!(a.demand(Money(1), &mu, food), Quantity::ZERO);
+    }
+}
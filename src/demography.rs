@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, HashMap};
+
+use failure::Error;
+use rand::Rng;
+use rand::prelude::SmallRng;
+use tracing::{debug, warn};
+
+use crate::agent::{Agent, AgentId, Agents};
+use crate::goods::{Good, Goods};
+use crate::ledger::{Entry, Ledger};
+use crate::market::Market;
+use crate::region::RegionId;
+use crate::scenario::Population;
+use crate::units::{Money, Quantity};
+use crate::utility::Preferences;
+
+/// When well-fed agents have children
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Births {
+    /// least units held of the goods it eats, after eating and working, to have a child
+    pub min_food: Quantity,
+    /// least cash held to have a child
+    #[serde(default)]
+    pub min_cash: Money,
+    /// chance each tick that an agent who qualifies has a child
+    pub rate: f64,
+    /// fraction of the parent's cash and goods the child starts with
+    pub share: f64,
+    /// each of the parent's skills is scaled by a random factor within this fraction of 1
+    #[serde(default)]
+    pub mutation: f64,
+}
+
+/// What happens to the cash and goods of the dead
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Estates {
+    /// they leave the economy
+    #[default]
+    Lost,
+    /// split equally between its living children, lost if it has none
+    Inherited,
+    /// goods sold to the orders left standing in its market, then inherited
+    Auctioned,
+}
+
+/// Newcomers from outside the economy, bringing their cash and goods
+#[derive(Clone, Debug)]
+pub struct Immigration {
+    /// chance each tick that a group arrives
+    pub rate: f64,
+    /// who arrives, `count` of them at a time
+    pub population: Population,
+    pub home: RegionId,
+    pub preferences: Preferences,
+}
+
+/// Agents being born, dying and moving in, the scenario only sets who is there at the start
+#[derive(Clone, Debug)]
+pub struct Demography {
+    pub births: Option<Births>,
+    pub estates: Estates,
+    pub immigration: Option<Immigration>,
+    /// parent of every agent born in the run
+    parents: BTreeMap<AgentId, AgentId>,
+    rng: SmallRng,
+}
+
+impl Demography {
+    pub fn new(births: Option<Births>, estates: Estates, immigration: Option<Immigration>, rng: SmallRng) -> Demography {
+        Demography { births, estates, immigration, parents: BTreeMap::new(), rng }
+    }
+
+    pub fn parent(&self, child: AgentId) -> Option<AgentId> {
+        self.parents.get(&child).cloned()
+    }
+
+    /// Living children of `id`, oldest first
+    pub fn heirs(&self, agents: &Agents, id: AgentId) -> Vec<AgentId> {
+        self.parents.iter()
+            .filter(|&(child, &parent)| parent == id && agents.contains_key(child))
+            .map(|(&child, _)| child)
+            .collect()
+    }
+
+    /// Hand the estate of `dead`, still in `agents`, to its heirs, selling its goods in
+    /// `market` first if estates are auctioned. Whatever is left goes with it.
+    pub fn settle(&mut self, agents: &mut Agents, dead: AgentId, market: &mut dyn Market, ledger: &mut Ledger) {
+        if self.estates == Estates::Lost {
+            return;
+        }
+        if self.estates == Estates::Auctioned {
+            for (good, amt) in sorted(&agents[&dead].res) {
+                match market.sell(agents, dead, good, amt, ledger) {
+                    Ok(fill) => debug!(agent = dead, %good, sold = %fill.qty, value = %fill.value, "auctioned"),
+                    Err(e) => warn!(agent = dead, %good, "auction failed: {}", e),
+                }
+            }
+        }
+        let heirs = self.heirs(agents, dead);
+        if heirs.is_empty() {
+            return;
+        }
+        let n = heirs.len() as i64;
+        let estate = agents[&dead].clone();
+        for (i, &heir) in heirs.iter().enumerate() {
+            // the eldest gets what doesn't split evenly
+            let split = |total: i64| total / n + if i == 0 { total % n } else { 0 };
+            let cash = Money(split(estate.cash.0));
+            let res: Vec<_> = sorted(&estate.res).into_iter()
+                .map(|(g, amt)| (g, Quantity(split(amt.0 as i64) as i32)))
+                .filter(|&(_, amt)| amt > Quantity::ZERO)
+                .collect();
+            if let Err(e) = gift(agents, dead, heir, cash, res, ledger) {
+                warn!(agent = dead, heir, "estate not handed on: {}", e);
+            }
+        }
+    }
+
+    /// New agents this tick: children of agents that eat and qualify for `births`, then any
+    /// immigrants. Ids are taken from `next_id` on.
+    /// Returns each newcomer and its parent, none for immigrants.
+    pub fn grow(&mut self, agents: &mut Agents, goods: &Goods, next_id: &mut AgentId,
+                ledger: &mut Ledger) -> Vec<(AgentId, Option<AgentId>)> {
+        let mut born = Vec::new();
+        if let Some(b) = self.births {
+            let parents: Vec<AgentId> = agents.values()
+                .filter(|a| a.food() >= b.min_food && a.cash >= b.min_cash && a.diet().is_some())
+                .map(|a| a.id)
+                .collect();
+            for parent in parents {
+                if *next_id == AgentId::MAX || self.rng.gen::<f64>() >= b.rate {
+                    continue;
+                }
+                let child = self.child(&agents[&parent], *next_id, &b);
+                let res = sorted(&agents[&parent].res).into_iter()
+                    .map(|(g, amt)| (g, amt.scale(b.share)))
+                    .filter(|&(_, amt)| amt > Quantity::ZERO)
+                    .collect();
+                let cash = agents[&parent].cash.scale(b.share);
+                agents.insert(child.id, child);
+                if let Err(e) = gift(agents, parent, *next_id, cash, res, ledger) {
+                    warn!(parent, child = *next_id, "child given nothing: {}", e);
+                }
+                self.parents.insert(*next_id, parent);
+                debug!(parent, child = *next_id, "born");
+                born.push((*next_id, Some(parent)));
+                *next_id += 1;
+            }
+        }
+
+        if let Some(im) = &self.immigration {
+            if self.rng.gen::<f64>() < im.rate {
+                for _ in 0..im.population.count {
+                    if *next_id == AgentId::MAX {
+                        break;
+                    }
+                    let a = im.population.agent(goods, *next_id, im.home, &im.preferences, &mut self.rng);
+                    let res = sorted(&a.res).into_iter().filter(|&(_, amt)| amt != Quantity::ZERO).collect();
+                    ledger.push(Entry::Immigrate { agent: a.id, cash: a.cash, res });
+                    debug!(agent = a.id, "immigrated");
+                    agents.insert(a.id, a);
+                    born.push((*next_id, None));
+                    *next_id += 1;
+                }
+            }
+        }
+        born
+    }
+
+    /// A penniless child of `parent` with its decision rules, tastes and home, and mutated skills
+    fn child(&mut self, parent: &Agent, id: AgentId, b: &Births) -> Agent {
+        let res = parent.res.keys().map(|&g| (g, Quantity::ZERO)).collect();
+        let skill = sorted(&parent.skill).into_iter()
+            .map(|(g, s)| {
+                let noise = if b.mutation > 0. { self.rng.gen_range(-b.mutation, b.mutation) } else { 0. };
+                (g, (s as f64 * (1. + noise)).max(0.) as f32)
+            })
+            .collect();
+        let mut child = Agent::new_with_id(id, Money::ZERO, res, skill);
+        child.home = parent.home;
+        child.hours = parent.hours;
+        child.strategy = parent.strategy.clone();
+        child.preferences = parent.preferences.clone();
+        child
+    }
+}
+
+/// Entries of `map` in good order, so draws and ledger entries don't depend on hashing
+fn sorted<T: Copy>(map: &HashMap<Good, T>) -> Vec<(Good, T)> {
+    let mut v: Vec<_> = map.iter().map(|(&g, &x)| (g, x)).collect();
+    v.sort_by_key(|&(g, _)| g);
+    v
+}
+
+/// Move `cash` and `res` from `giver` to `receiver`, both in `agents`.
+/// Nothing changes if any of the new holdings would overflow.
+fn gift(agents: &mut Agents, giver: AgentId, receiver: AgentId, cash: Money, res: Vec<(Good, Quantity)>,
+        ledger: &mut Ledger) -> Result<(), Error> {
+    let (from, to) = (&agents[&giver], &agents[&receiver]);
+    let (from_cash, to_cash) = (from.cash.checked_sub(cash)?, to.cash.checked_add(cash)?);
+    let mut from_res = Vec::with_capacity(res.len());
+    let mut to_res = Vec::with_capacity(res.len());
+    for &(g, amt) in &res {
+        from_res.push((g, from.res[&g].checked_sub(amt)?));
+        to_res.push((g, to.res.get(&g).cloned().unwrap_or(Quantity::ZERO).checked_add(amt)?));
+    }
+
+    let from = agents.get_mut(&giver).unwrap();
+    from.cash = from_cash;
+    from.res.extend(from_res);
+    let to = agents.get_mut(&receiver).unwrap();
+    to.cash = to_cash;
+    to.res.extend(to_res);
+    ledger.push(Entry::Gift { giver, receiver, cash, res });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::{btreemap, hashmap};
+    use rand::SeedableRng;
+
+    use crate::testing::{goods, market};
+    use crate::utility::Utility;
+
+    use super::*;
+
+    #[test]
+    fn births_and_estates() {
+        let (goods, [food, grain]) = goods(["Food", "Grain"]);
+        let mut agents = Agents::new();
+        let rich = Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(20), grain => Quantity(7)},
+                                       hashmap! {food => 1.0, grain => 2.0}).unwrap();
+        let poor = Agent::new_into_map(&mut agents, Money(10), hashmap! {food => Quantity(2), grain => Quantity(0)},
+                                       hashmap! {food => 1.0, grain => 1.0}).unwrap();
+        for a in agents.values_mut() {
+            let utility = Utility::Additive(btreemap! {food => vec![Money(50)]});
+            a.preferences = Preferences { utility, discount: 0.8, max: Quantity(5), perishable: BTreeMap::new() };
+        }
+        let births = Births { min_food: Quantity(10), min_cash: Money(50), rate: 1.0, share: 0.5, mutation: 0. };
+        let mut d = Demography::new(Some(births), Estates::Inherited, None, SmallRng::seed_from_u64(0));
+        let mut ledger = Ledger::default();
+        let mut next_id = 2;
+
+        // only the well-fed agent has a child, with half of everything and the same skills
+        assert_eq!(d.grow(&mut agents, &goods, &mut next_id, &mut ledger), vec![(2, Some(rich))]);
+        assert_eq!(next_id, 3);
+        let child = &agents[&2];
+        assert_eq!((child.cash, child.res[&food], child.res[&grain]), (Money(50), Quantity(10), Quantity(4)));
+        assert_eq!(child.skill[&grain], 2.0);
+        assert_eq!(agents[&rich].res[&grain], Quantity(3));
+        assert_eq!(d.heirs(&agents, rich), vec![2]);
+        assert!(d.heirs(&agents, poor).is_empty());
+
+        // the parent's estate goes to the child
+        d.settle(&mut agents, rich, &mut market(hashmap! {}), &mut ledger);
+        assert_eq!((agents[&rich].cash, agents[&2].cash, agents[&2].res[&food]), (Money::ZERO, Money(100), Quantity(20)));
+    }
+
+    #[test]
+    fn auctions_childless_estates() {
+        let (_, [food]) = goods(["Food"]);
+        let mut agents = Agents::new();
+        let dead = Agent::new_into_map(&mut agents, Money(10), hashmap! {food => Quantity(3)}, hashmap! {food => 1.0})
+            .unwrap();
+        let buyer = Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(0)}, hashmap! {food => 1.0})
+            .unwrap();
+        let mut market = market(hashmap! {food => Money(20)});
+        let mut ledger = Ledger::default();
+        market.trade((agents[&buyer].cash, buyer), food, Quantity(5)).unwrap();
+        market.execute_trades(&mut agents, &mut ledger);
+        let mut d = Demography::new(None, Estates::Auctioned, None, SmallRng::seed_from_u64(0));
+
+        // no heirs, but its food still goes to the standing bids
+        d.settle(&mut agents, dead, &mut market, &mut ledger);
+        assert_eq!((agents[&dead].cash, agents[&dead].res[&food]), (Money(70), Quantity(0)));
+        assert_eq!((agents[&buyer].cash, agents[&buyer].res[&food]), (Money(40), Quantity(3)));
+    }
+}
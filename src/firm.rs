@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::agent::{Agent, AgentId, MU};
+use crate::brain::Brain;
+use crate::goods::{Good, Task};
+use crate::market::Market;
+use crate::units::{Money, Quantity};
+
+/// Business owned by agents. Never works or eats itself: it keeps its inputs and capital goods
+/// stocked, hires agents to run its tasks through the labor market, sells everything it makes
+/// and pays the cash it doesn't need to its owners.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Firm {
+    /// names of the tasks it hires for
+    pub tasks: BTreeSet<String>,
+    /// stock of each input and tool it buys up to
+    pub targets: BTreeMap<Good, Quantity>,
+    /// goods it makes to sell
+    pub products: BTreeSet<Good>,
+    /// owners and their shares
+    pub owners: BTreeMap<AgentId, u32>,
+    /// cash kept for wages and inputs, the rest goes to the owners every tick
+    pub reserve: Money,
+    /// last market price seen for each of its products
+    pub prices: BTreeMap<Good, Money>,
+}
+
+impl Firm {
+    /// Firm running `tasks`, stocking the inputs and tools for `batches` runs of each
+    pub fn new(tasks: &[&Task], batches: u16, owners: BTreeMap<AgentId, u32>, reserve: Money) -> Firm {
+        let mut targets = BTreeMap::new();
+        let mut products = BTreeSet::new();
+        for task in tasks {
+            for (good, amt) in task.needs() {
+                *targets.entry(good).or_insert(Quantity::ZERO) += Quantity(amt.0.saturating_mul(batches as i32));
+            }
+            products.extend(task.outputs(1.0).map(|(g, _)| g));
+        }
+        // what it makes for its own use isn't for sale
+        let products = products.into_iter().filter(|g| !targets.contains_key(g)).collect();
+        let tasks = tasks.iter().map(|t| t.name.clone()).collect();
+        Firm { tasks, targets, products, owners, reserve, prices: BTreeMap::new() }
+    }
+
+    /// Least it sells a product worth `value` in its best use for, never nothing
+    fn reserve_price(value: Money) -> Money {
+        value.max(Money(1))
+    }
+
+    /// Units bought (positive) or sold (negative) at a posted `price`: inputs up to the target
+    /// while they're worth more than the price to it, all of its products if the price
+    /// covers what they're worth
+    pub fn demand(&self, good: Good, price: Money, value: Money, stock: Quantity) -> Quantity {
+        if self.products.contains(&good) {
+            return if price >= Firm::reserve_price(value) { -stock.max(Quantity::ZERO) } else { Quantity::ZERO };
+        }
+        match self.targets.get(&good) {
+            Some(&target) if price < value => (target - stock).max(Quantity::ZERO),
+            _ => Quantity::ZERO,
+        }
+    }
+
+    /// Bids at `value` for every unit short of the target it can pay for, every unit of its
+    /// products asked at the last market price, or their value if that's more
+    pub fn reservation_prices(&self, good: Good, value: Money, stock: Quantity, cash: Money) -> (Vec<Money>, Vec<Money>) {
+        if self.products.contains(&good) {
+            let price = self.prices.get(&good).cloned().unwrap_or(Money::ZERO);
+            let ask = price.max(Firm::reserve_price(value));
+            return (Vec::new(), vec![ask; stock.0.max(0) as usize]);
+        }
+        match self.targets.get(&good) {
+            Some(&target) if value > Money::ZERO => {
+                let affordable = (cash.0 / value.0).min((target - stock).0.max(0) as i64);
+                (vec![value; affordable as usize], Vec::new())
+            }
+            _ => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Each owner still `alive` and their share of the cash above the reserve, rounded down
+    pub fn dividends(&self, cash: Money, alive: impl Fn(AgentId) -> bool) -> Vec<(AgentId, Money)> {
+        let owners: Vec<_> = self.owners.iter().filter(|(&id, _)| alive(id)).collect();
+        let shares: u32 = owners.iter().map(|(_, &s)| s).sum();
+        let profit = cash - self.reserve;
+        if shares == 0 || profit <= Money::ZERO {
+            return Vec::new();
+        }
+        owners.into_iter()
+            .map(|(&id, &s)| (id, Money(profit.0 * s as i64 / shares as i64)))
+            .filter(|&(_, amt)| amt > Money::ZERO)
+            .collect()
+    }
+}
+
+impl Brain for Firm {
+    /// Inputs are worth what they make in their best use, the first unit's marginal utility
+    fn demand(&self, a: &Agent, price: Money, mu: &MU, good: Good) -> Quantity {
+        Firm::demand(self, good, price, mu.mu_buy(Quantity::ZERO), a.res[&good])
+    }
+
+    fn reservation_prices(&self, a: &Agent, mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        Firm::reservation_prices(self, good, mu.mu_buy(Quantity::ZERO), a.res[&good], a.cash)
+    }
+
+    fn choose_task<'a>(&self, _a: &Agent, _tasks: &'a [Task], _market: &dyn Market) -> Option<&'a Task> {
+        None
+    }
+
+    fn consume(&self, _a: &Agent, _food: Good, _mu: &MU, _max: Quantity) -> Option<Quantity> {
+        None
+    }
+
+    fn employs(&self, task: &Task) -> bool {
+        self.tasks.contains(&task.name)
+    }
+
+    fn observe(&mut self, good: Good, price: Money) {
+        if self.products.contains(&good) {
+            self.prices.insert(good, price);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::{btreemap, hashmap};
+    use rand::SeedableRng;
+    use rand::prelude::SmallRng;
+
+    use crate::agent::Agents;
+    use crate::brain::Strategy;
+    use crate::goods::Tool;
+    use crate::labor::LaborMarket;
+    use crate::ledger::Ledger;
+    use crate::market::Market;
+    use crate::order_book::OrderBookMarket;
+    use crate::testing::{goods, market};
+
+    use super::*;
+
+    #[test]
+    fn runs_a_mill() {
+        let (_, [flour, grain, stone]) = goods(["Flour", "Grain", "Millstone"]);
+        let tasks = [Task::new("Mill", &[(grain, Quantity(20))], (flour, Quantity(10)))
+            .with_tools(&[Tool { good: stone, qty: Quantity(1), life: 50 }])];
+        let firm = Firm::new(&[&tasks[0]], 3, btreemap! {0 => 1, 1 => 3}, Money(100));
+        assert_eq!(firm.targets, btreemap! {grain => Quantity(60), stone => Quantity(3)});
+
+        // buys grain up to the target while it's worth the price, sells all its flour
+        assert_eq!(firm.demand(grain, Money(2), Money(5), Quantity(50)), Quantity(10));
+        assert_eq!(firm.demand(grain, Money(6), Money(5), Quantity(50)), Quantity(0));
+        assert_eq!(firm.demand(flour, Money(6), Money(5), Quantity(7)), Quantity(-7));
+        // but not for less than the flour is worth
+        assert_eq!(firm.demand(flour, Money(1), Money(5), Quantity(7)), Quantity(0));
+        assert_eq!(firm.dividends(Money(500), |id| id != 2), vec![(0, Money(100)), (1, Money(300))]);
+        assert_eq!(firm.dividends(Money(500), |id| id == 1), vec![(1, Money(400))]);
+
+        // hires a worker to run the mill with its grain and millstone
+        let market = market(hashmap! {flour => Money(10), grain => Money(2), stone => Money(50)});
+        let mut agents = Agents::new();
+        let stock = hashmap! {flour => Quantity(0), grain => Quantity(40), stone => Quantity(1)};
+        let skill = hashmap! {flour => 1.0, grain => 1.0, stone => 1.0};
+        let f = Agent::new_into_map(&mut agents, Money(200), stock, skill.clone()).unwrap();
+        agents.get_mut(&f).unwrap().strategy = Strategy::Firm(firm.clone());
+        agents.get_mut(&f).unwrap().hours = 0;
+        let empty = hashmap! {flour => Quantity(0), grain => Quantity(0), stone => Quantity(0)};
+        let w = Agent::new_into_map(&mut agents, Money(0), empty, skill).unwrap();
+        let mut ledger = Ledger::default();
+
+        let hired = LaborMarket::new(Money(5), 0.1).hire(&mut agents, &[w], &[f, w], &tasks, &market, &mut ledger);
+        assert_eq!(hired, (8, 0));
+        assert_eq!((agents[&f].res[&flour], agents[&f].cash, agents[&w].cash), (Quantity(10), Money(160), Money(40)));
+    }
+
+    #[test]
+    fn sells_at_the_market_price() {
+        let (_, [food, grain]) = goods(["Food", "Grain"]);
+        let tasks = [Task::new("Bake", &[(grain, Quantity(25))], (food, Quantity(10)))];
+        let mut firm = Firm::new(&[&tasks[0]], 1, btreemap! {1 => 1}, Money(0));
+        firm.observe(food, Money(20));
+
+        let mut agents = Agents::new();
+        let skill = hashmap! {food => 1.0, grain => 1.0};
+        let f = Agent::new_into_map(&mut agents, Money(0), hashmap! {food => Quantity(5), grain => Quantity(0)}, skill.clone())
+            .unwrap();
+        agents.get_mut(&f).unwrap().strategy = Strategy::Firm(firm);
+        let mut market = OrderBookMarket::new(hashmap! {food => Money(20), grain => Money(5)}, SmallRng::seed_from_u64(0));
+        // nothing uses food, it's worth nothing to the firm but the market price
+        market.collect_orders(&agents, food, &MU(vec![(Money::ZERO, 0)]));
+        let b = Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(0), grain => Quantity(0)}, skill).unwrap();
+        market.trade((agents[&b].cash, b), food, Quantity(2)).unwrap();
+        let mut ledger = Ledger::default();
+        market.execute_trade(&mut agents, food, &mut ledger);
+
+        // the market order fills at the firm's resting asks
+        assert_eq!((agents[&f].cash, agents[&b].cash), (Money(40), Money(60)));
+        assert_eq!(agents[&b].res[&food], Quantity(2));
+    }
+}
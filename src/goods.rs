@@ -1,42 +1,280 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::RwLock;
+
 use arrayvec::ArrayVec;
-use serde::Serialize;
+use failure::Error;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as _;
 
 use crate::market::Market;
+use crate::units::{Money, Quantity};
+
+lazy_static! {
+    // interned good names, a good's id is its index
+    static ref NAMES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
 
-#[derive(Copy, Hash, Clone, Eq, PartialOrd, PartialEq, Ord, Debug, Serialize)]
-pub enum Good {
-    Food,
-    Grain,
+thread_local! {
+    // registry good names are deserialized against, see `Goods::resolving`
+    static REGISTRY: RefCell<Option<Goods>> = const { RefCell::new(None) };
 }
 
+/// Interned id of a good. Goods are created at runtime from a `Goods` registry,
+/// the same name always maps to the same id.
+#[derive(Copy, Hash, Clone, Eq, PartialOrd, PartialEq, Ord)]
+pub struct Good(u16);
+
 impl Good {
-    pub const ALL:[Good;2] = [Good::Food, Good::Grain];
+    pub fn named(name: &str) -> Good {
+        if let Some(i) = NAMES.read().unwrap().iter().position(|n| n == name) {
+            return Good(i as u16);
+        }
+        let mut names = NAMES.write().unwrap();
+        // may have been added between dropping the read lock and taking the write lock
+        match names.iter().position(|n| n == name) {
+            Some(i) => Good(i as u16),
+            None => {
+                names.push(name.to_owned());
+                Good((names.len() - 1) as u16)
+            }
+        }
+    }
+
+    pub fn name(self) -> String {
+        NAMES.read().unwrap()[self.0 as usize].clone()
+    }
+
+    pub fn id(self) -> u16 {
+        self.0
+    }
+}
+
+impl fmt::Debug for Good {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Good {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Serialize for Good {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Good {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Good, D::Error> {
+        let name = String::deserialize(d)?;
+        REGISTRY.with(|r| r.borrow().as_ref().and_then(|goods| goods.get(&name)))
+            .ok_or_else(|| D::Error::custom(format!("undefined good {}", name)))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GoodDef {
+    pub name: String,
+    /// fraction of the units held that goes bad every tick
+    #[serde(default)]
+    pub decay: f64,
+    /// paid every tick for each unit held
+    #[serde(default)]
+    pub storage: Money,
 }
 
+impl GoodDef {
+    /// A good that keeps forever for free
+    pub fn new(name: impl Into<String>) -> GoodDef {
+        GoodDef { name: name.into(), decay: 0., storage: Money::ZERO }
+    }
+
+    pub fn perishes(&self) -> bool {
+        self.decay > 0. || self.storage > Money::ZERO
+    }
+}
+
+/// The set of goods an economy trades, in definition order.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Goods {
+    goods: Vec<Good>,
+    defs: Vec<GoodDef>,
+}
+
+impl Goods {
+    pub fn new(defs: Vec<GoodDef>) -> Result<Goods, Error> {
+        let mut goods = Vec::with_capacity(defs.len());
+        for def in &defs {
+            let good = Good::named(&def.name);
+            if goods.contains(&good) {
+                bail!("good {} defined twice", def.name);
+            }
+            if def.decay < 0. || def.decay > 1. || def.storage < Money::ZERO {
+                bail!("good {} must decay by a fraction between 0 and 1 and cost nothing or more to store", def.name);
+            }
+            goods.push(good);
+        }
+        Ok(Goods { goods, defs })
+    }
+
+    pub fn from_names(names: &[&str]) -> Result<Goods, Error> {
+        Goods::new(names.iter()
+            .map(|&name| GoodDef::new(name))
+            .collect())
+    }
+
+    /// Run `f` with good names deserialized against this registry, so that a name
+    /// it doesn't define is an error rather than a new good
+    pub fn resolving<T>(&self, f: impl FnOnce() -> T) -> T {
+        let outer = REGISTRY.with(|r| r.replace(Some(self.clone())));
+        let res = f();
+        REGISTRY.with(|r| r.replace(outer));
+        res
+    }
+
+    pub fn get(&self, name: &str) -> Option<Good> {
+        self.defs.iter()
+            .position(|d| d.name == name)
+            .map(|i| self.goods[i])
+    }
+
+    pub fn def(&self, good: Good) -> &GoodDef {
+        let i = self.goods.iter()
+            .position(|&g| g == good)
+            .expect("good not in registry");
+        &self.defs[i]
+    }
+
+    pub fn contains(&self, good: Good) -> bool {
+        self.goods.contains(&good)
+    }
+
+    pub fn all(&self) -> &[Good] {
+        &self.goods
+    }
+
+    pub fn len(&self) -> usize {
+        self.goods.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.goods.is_empty()
+    }
+}
+
+
+/// Hours an agent works each tick and a task takes, unless set otherwise
+pub const DEFAULT_HOURS: u16 = 8;
+
+/// A good a task needs on hand without using it up. Every use wears it, one unit is used up
+/// after `life` uses.
+#[derive(Clone, Copy, Eq, PartialOrd, PartialEq, Ord, Debug, Serialize)]
+pub struct Tool {
+    pub good: Good,
+    pub qty: Quantity,
+    pub life: u16,
+}
 
 #[derive(Clone, Eq, PartialOrd, PartialEq, Ord, Debug, Serialize)]
 pub struct Task {
-    pub inputs: ArrayVec<[(Good, i16); 4]>,
-    pub output: (Good, i16),
+    pub inputs: ArrayVec<[(Good, Quantity); 4]>,
+    /// the main output, skill in this good sets how much of every output is made
+    pub output: (Good, Quantity),
+    /// further outputs made alongside `output`
+    pub byproducts: ArrayVec<[(Good, Quantity); 4]>,
+    pub tools: ArrayVec<[Tool; 4]>,
+    /// hours of work each time it's performed
+    pub labor: u16,
     pub name: String,
+    /// resource it takes units from each time it's performed, none for tasks that make
+    /// something from nothing but their inputs
+    pub draws: Option<(String, Quantity)>,
+    /// cash paid each time it's performed, rent for the resource it draws on
+    pub fee: Money,
 }
 
 impl Task {
-    pub fn value(&self, market: &dyn Market, skill: f32) -> (i16, i16, i16) {
-        let cost = self.inputs.iter()
-            .map(|(good, amt)| market.value(*good, *amt))
-            .sum();
-        let revenue = market.value(self.output.0, (self.output.1 as f32 * skill).round() as i16);
-        (revenue - cost, revenue, cost)
+    /// (profit, revenue, cost) at market prices, errors if any of them overflows
+    pub fn value(&self, market: &dyn Market, skill: f32) -> Result<(Money, Money, Money), Error> {
+        self.value_at(|g| market.price(g), skill)
     }
 
-    pub fn new(name: impl Into<String>, inputs: &[(Good, i16)], output: (Good, i16)) -> Task {
+    /// (profit, revenue, cost) with goods priced by `price`. The cost includes the wear
+    /// on tools, a unit's price spread over its life.
+    pub fn value_at(&self, price: impl Fn(Good) -> Money, skill: f32) -> Result<(Money, Money, Money), Error> {
+        let mut cost = self.fee;
+        for &(good, amt) in &self.inputs {
+            cost = cost.checked_add(price(good).checked_mul(amt)?)?;
+        }
+        for tool in &self.tools {
+            cost = cost.checked_add(price(tool.good).checked_div(Quantity(tool.life as i32))?)?;
+        }
+        let mut revenue = Money::ZERO;
+        for (good, amt) in self.outputs(skill) {
+            revenue = revenue.checked_add(price(good).checked_mul(amt)?)?;
+        }
+        Ok((revenue.checked_sub(cost)?, revenue, cost))
+    }
+
+    /// Units of output made by an agent with `skill`
+    pub fn output_amt(&self, skill: f32) -> Quantity {
+        self.output.1.scale(skill as f64)
+    }
+
+    /// Every good made by an agent with `skill`, the output first then the by-products
+    pub fn outputs(&self, skill: f32) -> impl Iterator<Item=(Good, Quantity)> + '_ {
+        std::iter::once(self.output)
+            .chain(self.byproducts.iter().cloned())
+            .map(move |(g, amt)| (g, amt.scale(skill as f64)))
+    }
+
+    /// Every good that must be held to perform the task, inputs then tools
+    pub fn needs(&self) -> impl Iterator<Item=(Good, Quantity)> + '_ {
+        self.inputs.iter().cloned()
+            .chain(self.tools.iter().map(|t| (t.good, t.qty)))
+    }
+
+    pub fn new(name: impl Into<String>, inputs: &[(Good, Quantity)], output: (Good, Quantity)) -> Task {
         let mut a = ArrayVec::new();
         a.try_extend_from_slice(inputs).unwrap();
         Task {
             name: name.into(),
             inputs: a,
             output,
+            byproducts: ArrayVec::new(),
+            tools: ArrayVec::new(),
+            labor: DEFAULT_HOURS,
+            draws: None,
+            fee: Money::ZERO,
         }
     }
+
+    /// Take `hours` to perform instead of a whole day
+    pub fn with_labor(mut self, hours: u16) -> Task {
+        self.labor = hours;
+        self
+    }
+
+    /// Also make `byproducts`, at most four
+    pub fn with_byproducts(mut self, byproducts: &[(Good, Quantity)]) -> Task {
+        self.byproducts.try_extend_from_slice(byproducts).unwrap();
+        self
+    }
+
+    /// Also need `tools`, at most four
+    pub fn with_tools(mut self, tools: &[Tool]) -> Task {
+        self.tools.try_extend_from_slice(tools).unwrap();
+        self
+    }
+
+    /// Take `amt` units of the resource `resource` each time it's performed
+    pub fn with_draw(mut self, resource: impl Into<String>, amt: Quantity) -> Task {
+        self.draws = Some((resource.into(), amt));
+        self
+    }
 }
@@ -0,0 +1,140 @@
+use tracing::{debug, warn};
+
+use crate::agent::{Agent, AgentId, Agents};
+use crate::goods::Task;
+use crate::ledger::{Entry, Ledger};
+use crate::market::{transfer_cash, Market};
+use crate::units::{Money, Quantity};
+
+/// Work for hire in one region at `wage` an hour. Once everyone has worked for themselves,
+/// agents with hours left work the task of whoever gains most from their hours, with the
+/// employer's goods and their own skill. The wage rises while jobs go unfilled and falls
+/// while workers go unhired.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaborMarket {
+    pub wage: Money,
+    /// fraction the wage moves each tick
+    pub gain: f64,
+}
+
+impl LaborMarket {
+    pub fn new(wage: Money, gain: f64) -> LaborMarket {
+        LaborMarket { wage, gain }
+    }
+
+    /// Hire out the hours left of `workers` to `employers`, then adjust the wage.
+    /// Returns the hours hired and the hours of workers nobody would hire.
+    pub fn hire(&mut self, agents: &mut Agents, workers: &[AgentId], employers: &[AgentId], tasks: &[Task],
+                market: &dyn Market, ledger: &mut Ledger) -> (u32, u32) {
+        let shortest = match tasks.iter().map(|t| t.labor.max(1)).min() {
+            Some(h) => h,
+            None => return (0, 0),
+        };
+        let (mut hired, mut unhired) = (0, 0);
+        for &w in workers {
+            while agents[&w].hours_left >= shortest {
+                let (e, task) = match self.best_job(agents, employers, w, tasks, market) {
+                    Some(job) => job,
+                    None => {
+                        unhired += agents[&w].hours_left as u32;
+                        break;
+                    }
+                };
+                let pay = self.wage.saturating_mul(Quantity(task.labor as i32));
+                let skill = agents[&w].productivity(task.output.0);
+                let employer = agents.get_mut(&e).unwrap();
+                if !employer.perform_task_with(task, skill, ledger) {
+                    break;
+                }
+                if let Err(err) = transfer_cash(agents, e, w, pay) {
+                    warn!(employer = e, worker = w, "wage not paid: {}", err);
+                    break;
+                }
+                let worker = agents.get_mut(&w).unwrap();
+                worker.hours_left = worker.hours_left.saturating_sub(task.labor.max(1));
+                ledger.push(Entry::Wage { employer: e, worker: w, hours: task.labor, pay });
+                hired += task.labor as u32;
+                debug!(employer = e, worker = w, task = %task.name, %pay, "hired");
+            }
+        }
+
+        // jobs that would still pay at the going wage, for a worker of average skill
+        let vacancies = employers.iter()
+            .any(|&e| tasks.iter().any(|t| self.surplus(&agents[&e], t, 1.0, market).is_some_and(|s| s > Money::ZERO)));
+        if vacancies && unhired == 0 {
+            self.wage = self.wage.nudge(self.gain);
+        } else if unhired > 0 && !vacancies {
+            // never work for nothing
+            self.wage = self.wage.nudge(-self.gain).max(Money(1));
+        }
+        (hired, unhired)
+    }
+
+    /// Employer and task that gain most over the wage from `worker`'s hours, none if no one gains
+    fn best_job<'a>(&self, agents: &Agents, employers: &[AgentId], worker: AgentId, tasks: &'a [Task],
+                    market: &dyn Market) -> Option<(AgentId, &'a Task)> {
+        let w = &agents[&worker];
+        let mut best = None;
+        let mut most = Money::ZERO;
+        for &e in employers.iter().filter(|&&e| e != worker) {
+            for task in tasks.iter().filter(|t| t.labor <= w.hours_left) {
+                match self.surplus(&agents[&e], task, w.productivity(task.output.0), market) {
+                    Some(s) if s > most => {
+                        best = Some((e, task));
+                        most = s;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        best
+    }
+
+    /// Profit `employer` makes on `task` worked at `skill` after paying the wage,
+    /// none if it lacks the goods or the cash or doesn't run the task
+    fn surplus(&self, employer: &Agent, task: &Task, skill: f32, market: &dyn Market) -> Option<Money> {
+        let pay = self.wage.checked_mul(Quantity(task.labor as i32)).ok()?;
+        if !employer.strategy.brain().employs(task) || employer.cash < pay || task.needs().any(|(g, amt)| employer.res[&g] < amt) {
+            return None;
+        }
+        let (profit, _, _) = task.value(market, skill).ok()?;
+        profit.checked_sub(pay).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::testing::{goods, market};
+
+    use super::*;
+
+    #[test]
+    fn hires_idle_hours() {
+        let (_, [food, grain]) = goods(["Food", "Grain"]);
+        let tasks = [Task::new("Bake", &[(grain, Quantity(25))], (food, Quantity(10))).with_labor(4)];
+        let market = market(hashmap! {food => Money(10), grain => Money(1)});
+        let mut agents = Agents::new();
+        let skill = hashmap! {food => 1.0, grain => 1.0};
+        let owner = Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(0), grain => Quantity(80)}, skill.clone())
+            .unwrap();
+        let worker = Agent::new_into_map(&mut agents, Money(0), hashmap! {food => Quantity(0), grain => Quantity(0)}, skill)
+            .unwrap();
+        agents.get_mut(&owner).unwrap().hours_left = 0;
+        let mut labor = LaborMarket::new(Money(5), 0.5);
+        let mut ledger = Ledger::default();
+
+        // baking pays 75 over the grain, 55 after 20 in wages, the worker has time to bake twice
+        assert_eq!(labor.hire(&mut agents, &[owner, worker], &[owner, worker], &tasks, &market, &mut ledger), (8, 0));
+        assert_eq!((agents[&owner].cash, agents[&owner].res[&food]), (Money(60), Quantity(20)));
+        assert_eq!((agents[&worker].cash, agents[&worker].hours_left), (Money(40), 0));
+        // no one left to bake the last 25 grain
+        assert_eq!(labor.wage, Money(8));
+
+        agents.get_mut(&owner).unwrap().res.insert(grain, Quantity(0));
+        agents.get_mut(&worker).unwrap().hours_left = 8;
+        assert_eq!(labor.hire(&mut agents, &[owner, worker], &[owner, worker], &tasks, &market, &mut ledger), (0, 8));
+        assert_eq!(labor.wage, Money(4));
+    }
+}
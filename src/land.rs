@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+
+use tracing::{debug, warn};
+
+use crate::agent::{Agent, AgentId, Agents};
+use crate::goods::Task;
+use crate::ledger::{Entry, Ledger};
+use crate::market::transfer_cash;
+use crate::region::RegionId;
+use crate::units::{Money, Quantity};
+
+/// A field, forest or deposit in one region, `stock` units of which tasks draw on. Regrows
+/// `regrowth` units a tick up to `capacity`, a deposit that doesn't just runs out. At most
+/// `limit` units are drawn from it a tick.
+/// Its owner draws on it for free, others pay the owner `rent` a unit. Rent rises while
+/// someone is turned away and falls while it lies idle. Nobody pays to use common land.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resource {
+    /// kind of resource, tasks draw on any resource of the kind they name
+    pub name: String,
+    pub region: RegionId,
+    pub stock: Quantity,
+    pub capacity: Quantity,
+    pub regrowth: Quantity,
+    pub limit: Quantity,
+    /// none for common land
+    pub owner: Option<AgentId>,
+    pub rent: Money,
+    /// fraction the rent moves each tick
+    pub gain: f64,
+    /// units drawn this tick
+    pub drawn: Quantity,
+    /// whether an agent found it used up this tick
+    short: bool,
+}
+
+impl Resource {
+    pub fn new(name: impl Into<String>, region: RegionId, capacity: Quantity, regrowth: Quantity, limit: Quantity)
+               -> Resource {
+        Resource {
+            name: name.into(),
+            region,
+            stock: capacity,
+            capacity,
+            regrowth,
+            limit,
+            owner: None,
+            rent: Money::ZERO,
+            gain: 0.,
+            drawn: Quantity::ZERO,
+            short: false,
+        }
+    }
+
+    /// Owned by `owner`, charging others `rent` a unit moved by `gain` each tick
+    pub fn owned_by(mut self, owner: AgentId, rent: Money, gain: f64) -> Resource {
+        self.owner = Some(owner);
+        self.rent = rent;
+        self.gain = gain;
+        self
+    }
+
+    /// Units that can still be drawn this tick
+    pub fn available(&self) -> Quantity {
+        self.stock.min(self.limit - self.drawn).max(Quantity::ZERO)
+    }
+
+    /// Rent agent `id` pays for `amt` units
+    fn rent_for(&self, id: AgentId, amt: Quantity) -> Money {
+        match self.owner {
+            Some(owner) if owner != id => self.rent.saturating_mul(amt),
+            _ => Money::ZERO,
+        }
+    }
+}
+
+/// Every resource in the economy, with the rules for drawing on them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Land {
+    resources: Vec<Resource>,
+}
+
+impl Land {
+    pub fn new(resources: Vec<Resource>) -> Land {
+        Land { resources }
+    }
+
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    /// Resource agent `a` would draw `amt` units of `name` from: its own, then common land,
+    /// then the cheapest it can pay the rent for. None if all of them are used up.
+    fn find(&self, name: &str, a: &Agent, amt: Quantity) -> Option<usize> {
+        self.resources.iter().enumerate()
+            .filter(|(_, r)| r.name == name && r.region == a.home && r.available() >= amt)
+            .filter(|(_, r)| r.rent_for(a.id, amt) <= a.cash)
+            .min_by_key(|(_, r)| (r.owner != Some(a.id), r.owner.is_some(), r.rent_for(a.id, amt)))
+            .map(|(i, _)| i)
+    }
+
+    /// The `tasks` agent `a` can find land for, each charging the rent it would pay.
+    /// Resources are marked short if it finds all of them used up, not if it can't pay.
+    pub fn open<'t>(&mut self, tasks: &'t [Task], a: &Agent) -> Cow<'t, [Task]> {
+        if tasks.iter().all(|t| t.draws.is_none()) {
+            return Cow::Borrowed(tasks);
+        }
+        let mut open = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let (name, amt) = match &task.draws {
+                Some((name, amt)) => (name, *amt),
+                None => {
+                    open.push(task.clone());
+                    continue;
+                }
+            };
+            match self.find(name, a, amt) {
+                Some(i) => {
+                    let mut task = task.clone();
+                    task.fee = self.resources[i].rent_for(a.id, amt);
+                    open.push(task);
+                }
+                None => {
+                    let local = |r: &Resource| &r.name == name && r.region == a.home;
+                    if !self.resources.iter().any(|r| local(r) && r.available() >= amt) {
+                        for r in self.resources.iter_mut().filter(|r| local(r)) {
+                            r.short = true;
+                        }
+                    }
+                }
+            }
+        }
+        Cow::Owned(open)
+    }
+
+    /// Draw what `task` takes from the land for agent `id`, paying the rent.
+    /// False if there's nothing left it can pay for, true for tasks that don't draw on land.
+    pub fn draw(&mut self, agents: &mut Agents, id: AgentId, task: &Task, ledger: &mut Ledger) -> bool {
+        let (name, amt) = match &task.draws {
+            Some((name, amt)) => (name, *amt),
+            None => return true,
+        };
+        let i = match self.find(name, &agents[&id], amt) {
+            Some(i) => i,
+            None => return false,
+        };
+        let r = &mut self.resources[i];
+        let rent = r.rent_for(id, amt);
+        if let (Some(owner), true) = (r.owner, rent > Money::ZERO) {
+            if let Err(e) = transfer_cash(agents, id, owner, rent) {
+                warn!(resource = %name, tenant = id, owner, "rent not paid: {}", e);
+                return false;
+            }
+            ledger.push(Entry::Rent { tenant: id, owner, amt: rent });
+        }
+        r.stock -= amt;
+        r.drawn += amt;
+        debug!(resource = %name, %amt, %rent, stock = %r.stock, "drew on land");
+        true
+    }
+
+    /// End the tick: move rents, regrow every resource and start counting draws afresh
+    pub fn grow(&mut self) {
+        for r in &mut self.resources {
+            if r.owner.is_some() {
+                if r.short {
+                    r.rent = r.rent.nudge(r.gain);
+                } else if r.drawn == Quantity::ZERO {
+                    r.rent = r.rent.nudge(-r.gain).max(Money::ZERO);
+                }
+            }
+            r.stock = (r.stock + r.regrowth).min(r.capacity);
+            r.drawn = Quantity::ZERO;
+            r.short = false;
+        }
+    }
+
+    /// Land of the dead agent `dead` goes to `heir`, or becomes common land
+    pub fn bequeath(&mut self, dead: AgentId, heir: Option<AgentId>) {
+        for r in self.resources.iter_mut().filter(|r| r.owner == Some(dead)) {
+            r.owner = heir;
+            if heir.is_none() {
+                r.rent = Money::ZERO;
+            }
+            debug!(resource = %r.name, ?heir, "land passed on");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::testing::goods;
+
+    use super::*;
+
+    #[test]
+    fn fields_run_out_and_charge_rent() {
+        let (_, [grain]) = goods(["Grain"]);
+        let tasks = [Task::new("Farm", &[], (grain, Quantity(10))).with_draw("Field", Quantity(10))];
+        let farm = &tasks[0];
+        let mut agents = Agents::new();
+        let owner = Agent::new_into_map(&mut agents, Money(0), hashmap! {grain => Quantity(0)}, hashmap! {grain => 1.0}).unwrap();
+        let tenant = Agent::new_into_map(&mut agents, Money(100), hashmap! {grain => Quantity(0)}, hashmap! {grain => 1.0})
+            .unwrap();
+        let field = Resource::new("Field", 0, Quantity(30), Quantity(5), Quantity(20)).owned_by(owner, Money(2), 0.5);
+        let mut land = Land::new(vec![field]);
+        let mut ledger = Ledger::default();
+
+        // the owner farms for free, the tenant pays 2 a unit
+        assert_eq!(land.open(&tasks, &agents[&owner])[0].fee, Money::ZERO);
+        assert_eq!(land.open(&tasks, &agents[&tenant])[0].fee, Money(20));
+        assert!(land.draw(&mut agents, owner, farm, &mut ledger));
+        assert!(land.draw(&mut agents, tenant, farm, &mut ledger));
+        assert_eq!((agents[&owner].cash, agents[&tenant].cash), (Money(20), Money(80)));
+
+        // at the harvest limit, the rent goes up and the field regrows 5
+        assert!(land.open(&tasks, &agents[&tenant]).is_empty());
+        assert!(!land.draw(&mut agents, tenant, farm, &mut ledger));
+        land.grow();
+        assert_eq!((land.resources()[0].stock, land.resources()[0].rent), (Quantity(15), Money(3)));
+        assert!(land.draw(&mut agents, owner, farm, &mut ledger));
+        // only 5 left, nobody else could have drawn on it
+        assert!(!land.draw(&mut agents, tenant, farm, &mut ledger));
+
+        // the dead owner's field becomes common land
+        land.bequeath(owner, None);
+        land.grow();
+        assert_eq!(land.open(&tasks, &agents[&tenant])[0].fee, Money::ZERO);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+
+use failure::Error;
+use rand::Rng;
+use rand::prelude::SmallRng;
+use tracing::{debug, trace};
+
+use crate::agent::{Agent, MU};
+use crate::brain::{Brain, Household};
+use crate::goods::{Good, Task};
+use crate::market::Market;
+use crate::units::{Money, Quantity};
+
+/// How a learner turns the prices it has seen into the price it expects next
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expectation {
+    /// move the expected price a fraction `gain` of the way to each new price
+    Adaptive { gain: f64 },
+    /// adaptive, plus the recent change in price smoothed by `trend_gain`, extrapolated a round
+    Trend { gain: f64, trend_gain: f64 },
+}
+
+/// How a learner picks its task
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskChoice {
+    /// the most profitable at expected prices, among those it has the inputs for
+    #[default]
+    Expected,
+    /// the best average realized profit, averaged with weight `rate` on the latest.
+    /// A random task with probability `explore`, tasks never tried are valued at expected prices.
+    Reinforcement { rate: f64, explore: f64 },
+}
+
+/// What a learner has made of one good's prices
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize)]
+pub struct Belief {
+    pub level: f64,
+    pub trend: f64,
+}
+
+/// Household that remembers prices and profits instead of taking the current prices at face
+/// value. Tasks are picked by `choice`, and when trading every unit is worth at least
+/// `patience` times the price it expects to sell it for later.
+#[derive(Clone, Debug, Serialize)]
+pub struct Learner {
+    pub expectation: Expectation,
+    pub choice: TaskChoice,
+    /// discount on expected resale value, 0 to value goods like a household
+    pub patience: f64,
+    pub beliefs: BTreeMap<Good, Belief>,
+    /// average realized profit of each task tried, by name
+    pub task_values: BTreeMap<String, f64>,
+    /// decides whether and where the next task choice explores, drawn anew after each task
+    draw: u64,
+    #[serde(skip)]
+    rng: SmallRng,
+}
+
+/// Learners are equal if they would decide alike, whatever state their rng is in
+impl PartialEq for Learner {
+    fn eq(&self, other: &Learner) -> bool {
+        (self.expectation, self.choice, self.patience, &self.beliefs, &self.task_values, self.draw)
+            == (other.expectation, other.choice, other.patience, &other.beliefs, &other.task_values, other.draw)
+    }
+}
+
+impl Learner {
+    /// `rng` draws where it explores
+    pub fn new(expectation: Expectation, choice: TaskChoice, patience: f64, mut rng: SmallRng) -> Learner {
+        Learner {
+            expectation,
+            choice,
+            patience,
+            beliefs: BTreeMap::new(),
+            task_values: BTreeMap::new(),
+            draw: rng.gen(),
+            rng,
+        }
+    }
+
+    /// Price expected for `good`, none before it has seen one
+    pub fn forecast(&self, good: Good) -> Option<Money> {
+        self.beliefs.get(&good).map(|b| Money((b.level + b.trend).round().max(0.) as i64))
+    }
+
+    /// Profit of `task` at expected prices, current prices for goods not seen yet
+    pub fn expected_profit(&self, task: &Task, market: &dyn Market, skill: f32) -> Result<Money, Error> {
+        let (profit, _, _) = task.value_at(|g| self.forecast(g).unwrap_or_else(|| market.price(g)), skill)?;
+        Ok(profit)
+    }
+
+    /// `mu` with every unit held, and one more, worth at least the discounted expected price
+    /// of `good`, so it speculates at most a unit at a time
+    fn with_resale(&self, mu: &MU, good: Good, held: Quantity) -> MU {
+        match self.forecast(good) {
+            Some(f) if self.patience > 0. => {
+                let floor = f.scale(self.patience);
+                let mut mu: Vec<_> = mu.0.iter().map(|&(m, i)| (m.max(floor), i)).collect();
+                // `MU` treats the last entry as the end of the schedule, so pad one past the next unit
+                let len = mu.len().max(held.0.max(0) as usize + 2);
+                mu.resize(len, (floor, 0));
+                MU(mu)
+            }
+            _ => mu.clone(),
+        }
+    }
+
+    fn value_or_zero(&self, task: &Task, a: &Agent, market: &dyn Market) -> Money {
+        self.expected_profit(task, market, a.productivity(task.output.0)).unwrap_or(Money::ZERO)
+    }
+}
+
+impl Brain for Learner {
+    fn demand(&self, a: &Agent, price: Money, mu: &MU, good: Good) -> Quantity {
+        Household.demand(a, price, &self.with_resale(mu, good, a.res[&good]), good)
+    }
+
+    fn reservation_prices(&self, a: &Agent, mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        Household.reservation_prices(a, &self.with_resale(mu, good, a.res[&good]), good)
+    }
+
+    fn choose_task<'a>(&self, a: &Agent, tasks: &'a [Task], market: &dyn Market) -> Option<&'a Task> {
+        let fits: Vec<&'a Task> = tasks.iter().filter(|t| t.labor <= a.hours_left).collect();
+        match self.choice {
+            TaskChoice::Expected => fits.into_iter()
+                .max_by_key(|&task| {
+                    let have_inputs = task.needs().all(|(g, amt)| a.res[&g] >= amt);
+                    if have_inputs { self.value_or_zero(task, a, market) } else { Money::ZERO }
+                }),
+            TaskChoice::Reinforcement { explore, .. } => {
+                // top 53 bits as a uniform float in [0, 1)
+                let uniform = (self.draw >> 11) as f64 / (1u64 << 53) as f64;
+                if !fits.is_empty() && uniform < explore {
+                    let task = fits[(self.draw % fits.len() as u64) as usize];
+                    trace!(task = %task.name, "exploring");
+                    return Some(task);
+                }
+                fits.into_iter()
+                    .max_by_key(|&task| match self.task_values.get(&task.name) {
+                        Some(&v) => Money(v.round() as i64),
+                        None => self.value_or_zero(task, a, market),
+                    })
+            }
+        }
+    }
+
+    fn consume(&self, a: &Agent, food: Good, mu: &MU, max: Quantity) -> Option<Quantity> {
+        Household.consume(a, food, mu, max)
+    }
+
+    fn observe(&mut self, good: Good, price: Money) {
+        let p = price.0 as f64;
+        let b = match self.beliefs.get_mut(&good) {
+            Some(b) => b,
+            None => {
+                self.beliefs.insert(good, Belief { level: p, trend: 0. });
+                return;
+            }
+        };
+        match self.expectation {
+            Expectation::Adaptive { gain } => b.level += gain * (p - b.level),
+            Expectation::Trend { gain, trend_gain } => {
+                let prev = b.level;
+                b.level += gain * (p - b.level);
+                b.trend += trend_gain * (b.level - prev - b.trend);
+            }
+        }
+    }
+
+    fn learn(&mut self, task: &Task, profit: Money) {
+        self.draw = self.rng.gen();
+        if let TaskChoice::Reinforcement { rate, .. } = self.choice {
+            let p = profit.0 as f64;
+            let v = self.task_values.entry(task.name.clone()).or_insert(p);
+            *v += rate * (p - *v);
+            debug!(task = %task.name, %profit, value = *v, "learned");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use rand::SeedableRng;
+
+    use crate::testing::{goods, market};
+
+    use super::*;
+
+    #[test]
+    fn expectations() {
+        let (_, [grain]) = goods(["Grain"]);
+        let rng = SmallRng::seed_from_u64(0);
+        let mut adaptive = Learner::new(Expectation::Adaptive { gain: 0.5 }, TaskChoice::Expected, 0., rng.clone());
+        let mut trend = Learner::new(Expectation::Trend { gain: 1.0, trend_gain: 0.5 }, TaskChoice::Expected, 0., rng);
+        assert_eq!(adaptive.forecast(grain), None);
+
+        for &p in &[10, 20, 30] {
+            adaptive.observe(grain, Money(p));
+            trend.observe(grain, Money(p));
+        }
+        // 10, then 15, then 22.5
+        assert_eq!(adaptive.forecast(grain), Some(Money(23)));
+        // level 30, trend half of 10 then 7.5
+        assert_eq!(trend.forecast(grain), Some(Money(38)));
+    }
+
+    #[test]
+    fn learns_task_values() {
+        let (_, [food, grain]) = goods(["Food", "Grain"]);
+        let tasks = [Task::new("Bake", &[(grain, Quantity(25))], (food, Quantity(10))),
+                     Task::new("Farm", &[], (grain, Quantity(10)))];
+        let market = market(hashmap! {food => Money(25), grain => Money(1)});
+        let a = Agent::new_with_id(0, Money(100), hashmap! {food => Quantity(3), grain => Quantity(30)},
+                           hashmap! {food => 1.0, grain => 1.0});
+        let mut l = Learner::new(Expectation::Adaptive { gain: 0.5 },
+                                 TaskChoice::Reinforcement { rate: 0.5, explore: 0. }, 0., SmallRng::seed_from_u64(0));
+
+        // untried, baking looks best at expected prices
+        assert_eq!(l.choose_task(&a, &tasks, &market).unwrap().name, "Bake");
+        l.learn(&tasks[0], Money(-10));
+        l.learn(&tasks[1], Money(10));
+        assert_eq!(l.choose_task(&a, &tasks, &market).unwrap().name, "Farm");
+        l.learn(&tasks[1], Money(-30));
+        assert_eq!(l.task_values["Farm"], -10.);
+        assert_eq!(l.choose_task(&a, &tasks, &market).unwrap().name, "Farm");
+        l.learn(&tasks[1], Money(-30));
+        assert_eq!(l.choose_task(&a, &tasks, &market).unwrap().name, "Bake");
+
+        // exploring, the choices only depend on the seed it was given
+        let explorer = |seed| {
+            let choice = TaskChoice::Reinforcement { rate: 0.5, explore: 1. };
+            let mut l = Learner::new(Expectation::Adaptive { gain: 0.5 }, choice, 0., SmallRng::seed_from_u64(seed));
+            (0..20).map(|_| {
+                let task = l.choose_task(&a, &tasks, &market).unwrap();
+                l.learn(task, Money(10));
+                task.name.clone()
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(explorer(1), explorer(1));
+        assert!(explorer(1).contains(&"Farm".to_string()) && explorer(1).contains(&"Bake".to_string()));
+    }
+
+    #[test]
+    fn holds_out_for_resale() {
+        let (_, [grain]) = goods(["Grain"]);
+        let a = Agent::new_with_id(0, Money(100), hashmap! {grain => Quantity(3)}, hashmap! {grain => 1.0});
+        let mu = MU(vec![(Money(10), 0), (Money(5), 1), (Money(2), 2)]);
+        let mut l = Learner::new(Expectation::Adaptive { gain: 0.5 }, TaskChoice::Expected, 0.8,
+                                 SmallRng::seed_from_u64(0));
+
+        // a household sells its last unit for 3
+        assert_eq!(Household.demand(&a, Money(3), &mu, grain), Quantity(-1));
+        l.observe(grain, Money(10));
+        // expects to get 10 later, worth 8 now, so it buys one more instead
+        assert_eq!(l.demand(&a, Money(3), &mu, grain), Quantity(1));
+        assert_eq!(l.demand(&a, Money(9), &mu, grain), Quantity(-2));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use failure::Error;
+
+use crate::agent::{AgentId, Agents};
+use crate::goods::Good;
+use crate::record::add;
+use crate::region::RegionId;
+use crate::units::{Money, Quantity};
+
+/// Every change to agents' cash and goods, in the order it happened
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    /// `qty` units of `good` from seller to buyer, `price` cash per unit the other way
+    Transfer { buyer: AgentId, seller: AgentId, good: Good, qty: Quantity, price: Money },
+    /// goods created by a task
+    Produce { agent: AgentId, good: Good, amt: Quantity },
+    /// goods used up, eaten or spent as task inputs
+    Consume { agent: AgentId, good: Good, amt: Quantity },
+    /// agent removed, taking its cash and goods with it
+    Death { agent: AgentId, cash: Money, res: Vec<(Good, Quantity)> },
+    /// goods sent towards region `to`, out of anyone's hands until they arrive,
+    /// and the `cost` of the transport, paid to nobody
+    Ship { agent: AgentId, good: Good, qty: Quantity, cost: Money, to: RegionId },
+    /// shipped goods handed back to their owner
+    Arrive { agent: AgentId, good: Good, qty: Quantity },
+    /// `pay` cash from employer to worker for `hours` of work
+    Wage { employer: AgentId, worker: AgentId, hours: u16, pay: Money },
+    /// `amt` cash from a firm to one of its owners
+    Dividend { firm: AgentId, owner: AgentId, amt: Money },
+    /// cash and goods given away for nothing, to a child or an heir
+    Gift { giver: AgentId, receiver: AgentId, cash: Money, res: Vec<(Good, Quantity)> },
+    /// agent arrived from outside the economy, bringing its cash and goods
+    Immigrate { agent: AgentId, cash: Money, res: Vec<(Good, Quantity)> },
+    /// goods gone bad while held
+    Spoil { agent: AgentId, good: Good, amt: Quantity },
+    /// `cost` of storing the units of `good` held through a tick, paid to nobody
+    Storage { agent: AgentId, good: Good, cost: Money },
+    /// `amt` cash from a tenant to the owner of the land it drew on
+    Rent { tenant: AgentId, owner: AgentId, amt: Money },
+}
+
+/// Append-only record of `Entry`s, tagged with the tick they happened in
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    entries: Vec<(u16, Entry)>,
+    tick: u16,
+}
+
+/// Total cash and goods held by all agents
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Totals {
+    pub cash: Money,
+    pub goods: BTreeMap<Good, Quantity>,
+}
+
+impl Totals {
+    pub fn of(agents: &Agents) -> Totals {
+        let mut totals = Totals::default();
+        for a in agents.values() {
+            totals.cash += a.cash;
+            for (&g, &amt) in &a.res {
+                *totals.goods.entry(g).or_insert(Quantity::ZERO) += amt;
+            }
+        }
+        totals
+    }
+}
+
+impl Ledger {
+    pub fn set_tick(&mut self, tick: u16) {
+        self.tick = tick;
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.entries.push((self.tick, entry));
+    }
+
+    pub fn transfer(&mut self, buyer: AgentId, seller: AgentId, good: Good, qty: Quantity, price: Money) {
+        self.push(Entry::Transfer { buyer, seller, good, qty, price });
+    }
+
+    pub fn produce(&mut self, agent: AgentId, good: Good, amt: Quantity) {
+        self.push(Entry::Produce { agent, good, amt });
+    }
+
+    pub fn consume(&mut self, agent: AgentId, good: Good, amt: Quantity) {
+        self.push(Entry::Consume { agent, good, amt });
+    }
+
+    pub fn entries(&self) -> &[(u16, Entry)] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Totals expected after applying the entries from `since` on to `before`
+    pub fn expected(&self, since: usize, before: &Totals) -> Totals {
+        let mut t = before.clone();
+        for (_, e) in &self.entries[since..] {
+            match e {
+                Entry::Transfer { .. } | Entry::Wage { .. } | Entry::Dividend { .. } | Entry::Gift { .. }
+                | Entry::Rent { .. } => {}
+                Entry::Produce { good, amt, .. } => *t.goods.entry(*good).or_insert(Quantity::ZERO) += *amt,
+                Entry::Consume { good, amt, .. } => *t.goods.entry(*good).or_insert(Quantity::ZERO) -= *amt,
+                Entry::Death { cash, res, .. } => {
+                    t.cash -= *cash;
+                    for (g, amt) in res {
+                        *t.goods.entry(*g).or_insert(Quantity::ZERO) -= *amt;
+                    }
+                }
+                Entry::Immigrate { cash, res, .. } => {
+                    t.cash += *cash;
+                    for (g, amt) in res {
+                        *t.goods.entry(*g).or_insert(Quantity::ZERO) += *amt;
+                    }
+                }
+                Entry::Ship { good, qty, cost, .. } => {
+                    t.cash -= *cost;
+                    *t.goods.entry(*good).or_insert(Quantity::ZERO) -= *qty;
+                }
+                Entry::Arrive { good, qty, .. } => *t.goods.entry(*good).or_insert(Quantity::ZERO) += *qty,
+                Entry::Spoil { good, amt, .. } => *t.goods.entry(*good).or_insert(Quantity::ZERO) -= *amt,
+                Entry::Storage { cost, .. } => t.cash -= *cost,
+            }
+        }
+        t
+    }
+
+    /// Check that cash was conserved and goods only changed through recorded
+    /// production, consumption, deaths, immigration, shipping, spoilage and storage since entry `since`
+    pub fn verify(&self, since: usize, before: &Totals, agents: &Agents) -> Result<(), Error> {
+        let expected = self.expected(since, before);
+        let actual = Totals::of(agents);
+        if expected.cash != actual.cash {
+            bail!("tick {}: total cash is {}, ledger says {}", self.tick, actual.cash, expected.cash);
+        }
+        // a good nobody holds any more is missing from `actual`
+        for g in actual.goods.keys().chain(expected.goods.keys()) {
+            let amt = actual.goods.get(g).cloned().unwrap_or(Quantity::ZERO);
+            let want = expected.goods.get(g).cloned().unwrap_or(Quantity::ZERO);
+            if want != amt {
+                bail!("tick {}: total {} is {}, ledger says {}", self.tick, g, amt, want);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the entries from `since` on to the "ledger" record
+    pub fn record(&self, since: usize) {
+        for (_, e) in &self.entries[since..] {
+            match e {
+                Entry::Transfer { buyer, seller, good, qty, price } =>
+                    add("ledger", ("transfer", buyer, seller, good.name(), qty, price)),
+                Entry::Produce { agent, good, amt } =>
+                    add("ledger", ("produce", agent, "", good.name(), amt, "")),
+                Entry::Consume { agent, good, amt } =>
+                    add("ledger", ("consume", agent, "", good.name(), amt, "")),
+                Entry::Death { agent, cash, res } => {
+                    add("ledger", ("death", agent, "", "", "", cash));
+                    for (g, amt) in res {
+                        add("ledger", ("estate", agent, "", g.name(), amt, ""));
+                    }
+                }
+                // total transport cost in the price column, the destination as the other party
+                Entry::Ship { agent, good, qty, cost, to } =>
+                    add("ledger", ("ship", agent, to, good.name(), qty, cost)),
+                Entry::Arrive { agent, good, qty } =>
+                    add("ledger", ("arrive", agent, "", good.name(), qty, "")),
+                // hours worked in the amount column
+                Entry::Wage { employer, worker, hours, pay } =>
+                    add("ledger", ("wage", employer, worker, "", hours, pay)),
+                Entry::Dividend { firm, owner, amt } =>
+                    add("ledger", ("dividend", firm, owner, "", "", amt)),
+                Entry::Gift { giver, receiver, cash, res } => {
+                    add("ledger", ("gift", giver, receiver, "", "", cash));
+                    for (g, amt) in res {
+                        add("ledger", ("gift", giver, receiver, g.name(), amt, ""));
+                    }
+                }
+                Entry::Immigrate { agent, cash, res } => {
+                    add("ledger", ("immigrate", agent, "", "", "", cash));
+                    for (g, amt) in res {
+                        add("ledger", ("immigrate", agent, "", g.name(), amt, ""));
+                    }
+                }
+                Entry::Spoil { agent, good, amt } =>
+                    add("ledger", ("spoil", agent, "", good.name(), amt, "")),
+                Entry::Storage { agent, good, cost } =>
+                    add("ledger", ("storage", agent, "", good.name(), "", cost)),
+                Entry::Rent { tenant, owner, amt } =>
+                    add("ledger", ("rent", tenant, owner, "", "", amt)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::agent::Agent;
+    use crate::market::transfer;
+    use crate::testing::goods;
+
+    use super::*;
+
+    #[test]
+    fn catches_unrecorded_changes() {
+        let (_, [food]) = goods(["Food"]);
+        let mut agents = Agents::new();
+        for _ in 0..2 {
+            Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(5)}, hashmap! {food => 1.0}).unwrap();
+        }
+        let (mut ledger, before) = (Ledger::default(), Totals::of(&agents));
+
+        transfer(&mut agents, 0, 1, food, Money(7), &mut ledger).unwrap();
+        *agents.get_mut(&1).unwrap().res.get_mut(&food).unwrap() += Quantity(3);
+        ledger.produce(1, food, Quantity(3));
+        assert!(ledger.verify(0, &before, &agents).is_ok());
+
+        // cash from nowhere
+        agents.get_mut(&0).unwrap().cash += Money(1);
+        assert!(ledger.verify(0, &before, &agents).is_err());
+        agents.get_mut(&0).unwrap().cash -= Money(1);
+
+        // eaten without a record
+        *agents.get_mut(&0).unwrap().res.get_mut(&food).unwrap() -= Quantity(1);
+        assert!(ledger.verify(0, &before, &agents).is_err());
+
+        // gone from every agent without a record
+        for a in agents.values_mut() {
+            a.res.remove(&food);
+        }
+        assert!(ledger.verify(0, &before, &agents).is_err());
+    }
+}
@@ -1,5 +1,3 @@
-#![feature(in_band_lifetimes)]
-#![feature(generators, cell_update)]
 #![allow(unused_imports, dead_code)]
 
 #[macro_use]
@@ -10,10 +8,28 @@ extern crate maplit;
 extern crate serde_derive;
 
 pub mod market;
+pub mod merchant;
+pub mod order_book;
+pub mod price_adjust;
+pub mod firm;
 pub mod goods;
+pub mod labor;
+pub mod land;
+pub mod learner;
+pub mod ledger;
 pub mod agent;
+pub mod brain;
+pub mod demography;
+pub mod recipe;
 pub mod record;
+pub mod region;
+pub mod scenario;
+pub mod simulation;
+pub mod summary;
+pub mod tatonnement;
+pub mod trader;
+pub mod units;
+pub mod utility;
 
-
-
-
+#[cfg(test)]
+mod testing;
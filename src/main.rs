@@ -1,130 +1,185 @@
 #![allow(unused_imports, dead_code, unused_variables, unused_must_use)]
 
-use std::cell::{Cell, RefCell};
-use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io;
-use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
+
+use market_sim1::record::{flush, init_recorder};
+use market_sim1::scenario::Scenario;
+use market_sim1::simulation::Simulation;
+use market_sim1::summary::Summary;
+
+/// Scenario saved next to a run's csv files, with the seed filled in so it can be replayed
+const SAVED_SCENARIO: &str = "scenario.json";
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "market_sim1", about = "Agent based market simulation")]
+struct Opt {
+    /// More output, repeat for more detail (info, debug, trace)
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+
+    /// Log filter, overrides -v and $MARKET_SIM_LOG.
+    /// e.g. `market_sim1::market=trace` or `[agent{id=3}]=trace` or `[good{good=Food}]=debug`
+    #[structopt(long, global = true)]
+    log: Option<String>,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
 
-use arrayvec::ArrayVec;
-use maplit::hashmap;
-use rand::prelude::SmallRng;
-use rand::SeedableRng;
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Run a scenario once
+    Run {
+        #[structopt(flatten)]
+        run: RunOpts,
+    },
+    /// Run a scenario once per seed, starting at --seed (default 0)
+    Sweep {
+        #[structopt(flatten)]
+        run: RunOpts,
+        /// Number of runs
+        #[structopt(long, default_value = "10")]
+        runs: u64,
+    },
+    /// Re-run a recorded run exactly, using the scenario and seed saved in its directory
+    Replay {
+        /// Directory of the recorded run
+        dir: PathBuf,
+        /// Output directory, defaults to the parent of the recorded run
+        #[structopt(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Print headline numbers of a recorded run
+    Summarize {
+        /// Directory of the recorded run
+        dir: PathBuf,
+    },
+}
 
-use market_sim1;
-use market_sim1::agent::{Agent, AgentId, MU};
-use market_sim1::goods::{Good::{Food, Grain}, Good, Task};
-use market_sim1::market::{ClearingMarket, GoodMap, Market, UnexecutedTrades};
-use market_sim1::record::{add, flush, init_recorder, register, set_tick};
-use std::io::{Write, repeat};
+#[derive(StructOpt, Debug)]
+struct RunOpts {
+    /// Scenario file (.toml, .ron or .json)
+    #[structopt(short, long, default_value = "scenarios/bread.toml")]
+    scenario: PathBuf,
+    /// Master seed, overrides the scenario's seed
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Directory runs are recorded into
+    #[structopt(short, long, default_value = "data")]
+    out: PathBuf,
+    /// Run name, defaults to the scenario file name
+    #[structopt(short, long)]
+    name: Option<String>,
+    /// Number of ticks, overrides the scenario's tick count
+    #[structopt(short, long)]
+    ticks: Option<u16>,
+    /// Overwrite an earlier run with the same name instead of adding a random suffix
+    #[structopt(long)]
+    overwrite: bool,
+    /// Check after every tick that cash and goods are conserved, overrides the scenario
+    #[structopt(long)]
+    check: bool,
+}
 
 fn main() {
-    dbg!("start");
-    std::io::stdout().flush();
-    init_recorder("adapt v2", true);
-    let tasks = vec![
-        Task::new("Bake", &[(Grain, 25)], (Food, 10)),
-        Task::new("Farm", &[], (Grain, 10)),
-    ];
-
-    dbg!("here");
-    let agents = Agent::pre_made(15);
-
-    let market = ClearingMarket::new(hashmap! {
-        Food => 25,
-        Grain => 5,
-    });
-    dbg!("here2");
-
-    register("deaths", &["agent_id"]);
-    register("tasks", &["task_name", "task_value", "revenue", "cost", "agent_id"]);
-    register("price", &["good", "new_price", "old_price", "unexecuted", "volume"]);
-    register("agent_info", &["agent_id", "cash", "food", "grain"]);
-    register("utility", &["agent_id", "utility", "food_consumed"]);
-    register("trades", &["good", "price", "supply", "to_trade", "agent_id"]);
-
-    dbg!("running...");
-    run(tasks, agents, market, 50);
+    let opt = Opt::from_args();
+    init_logging(opt.verbose, opt.log.as_ref());
+    if let Err(e) = run_command(opt) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
 
-    flush();
+/// Silent apart from warnings unless asked for more
+fn init_logging(verbose: u8, filter: Option<&String>) {
+    let default = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = filter.cloned()
+        .or_else(|| std::env::var("MARKET_SIM_LOG").ok())
+        .unwrap_or_else(|| default.to_string());
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(filter))
+        .with_writer(std::io::stderr)
+        .init();
 }
 
-fn run(tasks: Vec<Task>,
-       mut agents: HashMap<AgentId, Agent>,
-       mut market: impl Market,
-       max_iters: u16) {
-    let mut dead = HashSet::with_capacity(100);
-    let rng = SmallRng::from_entropy();
-
-    let food_mu = MU::from_curr_mu(&[120_i16, 60, 50, 40, 30, 20, 10, 2, 1], 0.8);
-    let food_utils = food_mu.utility(0);
-
-    for i in 0..max_iters {
-        set_tick(i);
-        println!("{}", i);
-//        add("price", ("Food", market.price(Food) ));
-//        add("price", ("Grain", market.price(Grain)));
-
-
-        for trade_round in 0..2 {
-            // register trades
-            for &good in &Good::ALL {
-                let price = market.price(good);
-                let mu = match good {
-                    Food => food_mu.clone(),
-                    _ => MU::from_market(&market, &tasks, good)
-                };
-                for a in agents.values() {
-                    let trade = a.choose_trade(price, &mu, good);
-                    println!("good, amt to trade {:?}, {:?}", good, trade);
-                    market.trade((a.cash, a.id), good, trade);
-                }
-            }
-            let res = market.execute_trades(&mut agents);
-            log_prices(&res, &market);
+fn run_command(opt: Opt) -> Result<(), Error> {
+    match opt.cmd {
+        Command::Run { run } => {
+            let scenario = run.load()?;
+            record_run(scenario, &run.out, &run.run_name(), run.overwrite, opt.verbose)?;
         }
-
-        // consume food
-        for a in agents.values_mut() {
-            let food = a.res[&Food] as usize;
-            if food <= 1 {
-                dead.insert(a.id);
+        Command::Sweep { run, runs } => {
+            let mut scenario = run.load()?;
+            let first = run.seed.unwrap_or(0);
+            for seed in first..first + runs {
+                scenario.seed = Some(seed);
+                let name = format!("{}_seed{}", run.run_name(), seed);
+                record_run(scenario.clone(), &run.out, &name, run.overwrite, opt.verbose)?;
             }
-            let consumption = 5.min(food_mu.mu_consume(food as i16));
-            add("utility", (a.id, food_utils[food.min(5)], consumption));
-            dbg!(food);
-            *a.res.get_mut(&Food).unwrap() -= consumption;
-            dbg!(a.res[&Food]);
         }
-
-        // remove dead agents
-        for a in &dead {
-            agents.remove(a);
-            add("deaths", a)
+        Command::Replay { dir, out } => {
+            let scenario = Scenario::load(dir.join(SAVED_SCENARIO))?;
+            let out = out.or_else(|| dir.parent().map(Path::to_path_buf))
+                .unwrap_or_else(|| PathBuf::from("."));
+            let name = dir.file_name()
+                .map_or("run".into(), |n| n.to_string_lossy().into_owned()) + "_replay";
+            record_run(scenario, &out, &name, true, opt.verbose)?;
         }
-        dead.clear();
-
-        // agents choose what to produce and produce it
-        for a in agents.values_mut() {
-            let task = a.choose_task(&tasks, &market);
-            add("tasks", (&task.name, task.value(&market, a.skill[&task.output.0]), a.id));
-            a.perform_task(task, &mut market);
-            println!("Id {} working {:?}", a.id, &task.name);
+        Command::Summarize { dir } => {
+            print!("{}", Summary::load(&dir)?);
         }
+    }
+    Ok(())
+}
 
-        for a in agents.values_mut() {
-            add("agent_info", (a.id, &a.cash, &a.res[&Food], &a.res[&Grain]))
+impl RunOpts {
+    fn load(&self) -> Result<Scenario, Error> {
+        let mut scenario = Scenario::load(&self.scenario)?;
+        if let Some(seed) = self.seed {
+            scenario.seed = Some(seed);
+        }
+        if let Some(ticks) = self.ticks {
+            scenario.ticks = ticks;
+        }
+        if self.check {
+            scenario.check_invariants = true;
         }
+        Ok(scenario)
+    }
+
+    fn run_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.scenario.file_stem()
+                .map_or("run".into(), |s| s.to_string_lossy().into_owned())
+        })
     }
 }
 
-fn log_prices(res: &GoodMap<UnexecutedTrades>, market: &dyn Market) {
-    for (&good, &t) in res {
-        let (un, vol) = match t {
-            UnexecutedTrades::Sells(un, vol) => (-un, vol),
-            UnexecutedTrades::Buys(un, vol) => (un, vol),
-            UnexecutedTrades::All(vol) => (0, vol)
-        };
-        add("price", (good, market.price(good), market.old_price(good), un, vol));
+/// Build and run the scenario to its horizon, recording into `out/name`
+fn record_run(mut scenario: Scenario, out: &Path, name: &str, overwrite: bool, verbose: u8) -> Result<(), Error> {
+    let mut sim = scenario.build()?;
+    let dir = init_recorder(out, name, overwrite);
+    // pin the seed actually used so the saved scenario replays this run
+    scenario.seed = Some(sim.seed());
+    serde_json::to_writer_pretty(File::create(dir.join(SAVED_SCENARIO))?, &scenario)?;
+
+    sim.register_records();
+    sim.run_to_end();
+    flush();
+
+    println!("{} (seed {})", dir.display(), sim.seed());
+    if verbose > 0 {
+        print!("{}", Summary::load(&dir)?);
     }
-}
\ No newline at end of file
+    Ok(())
+}
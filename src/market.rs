@@ -1,6 +1,6 @@
 use std::collections::hash_map::RandomState;
 use std::collections::{HashMap, VecDeque};
-use std::iter::{FromIterator, repeat};
+use std::iter::{FromIterator, repeat_n};
 use std::sync::atomic::Ordering::AcqRel;
 
 use failure::Error;
@@ -9,200 +9,332 @@ use rand::distributions::weighted::alias_method::WeightedIndex;
 use rand::prelude::{IteratorRandom, SliceRandom, SmallRng};
 use rand::SeedableRng;
 
-use crate::agent::{Agent, AgentId};
+use crate::agent::{Agent, AgentId, Agents, MU};
 use crate::goods::Good;
+use crate::ledger::Ledger;
 use crate::market::UnexecutedTrades::{All, Buys, Sells};
+use crate::price_adjust::{Adaptive, PriceAdjuster};
 use crate::record::add;
+use crate::units::{Money, Quantity};
+use tracing::{debug_span, error, trace, trace_span};
 
 pub type GoodMap<T> = LinearMap<Good, T>;
 
 pub trait Market {
-    fn price(&self, good: Good) -> i16;
-    fn old_price(&self, good: Good) -> i16;
+    /// Goods traded in this market
+    fn goods(&self) -> Vec<Good>;
 
-    fn values(&self, goods: &[(Good, i16)]) -> i16 {
+    fn price(&self, good: Good) -> Money;
+    fn old_price(&self, good: Good) -> Money;
+
+    fn values(&self, goods: &[(Good, Quantity)]) -> Result<Money, Error> {
         goods.iter()
-            .map(|&(g, amt)| self.value(g, amt))
-            .sum()
+            .try_fold(Money::ZERO, |total, &(g, amt)| total.checked_add(self.value(g, amt)?))
     }
 
-    fn trade(&mut self, cash_and_id: (i16, u16), good: Good, amt: i16) -> Result<(), Error>;
+    fn trade(&mut self, cash_and_id: (Money, AgentId), good: Good, amt: Quantity) -> Result<(), Error>;
 
-    fn execute_trade(&mut self, agents: &mut HashMap<AgentId, Agent>, good: Good) -> UnexecutedTrades;
+    /// Ask every agent for its orders in `good`, valuing it by `mu`.
+    /// By default agents are price takers and trade what they want at the posted price.
+    fn collect_orders(&mut self, agents: &Agents, good: Good, mu: &MU) {
+        let price = self.price(good);
+        for a in agents.values() {
+            let span = trace_span!("agent", id = a.id);
+            let _enter = span.enter();
+            let trade = a.choose_trade(price, mu, good);
+            // orders the agent can't afford are dropped
+            let _ = self.trade((a.cash, a.id), good, trade);
+        }
+    }
 
-    fn execute_trades(&mut self, agents: &mut HashMap<AgentId, Agent>) -> GoodMap<UnexecutedTrades> {
-        Good::ALL.iter()
-            .map(|&good| {
-                let unexecuted = self.execute_trade(agents, good);
+    fn execute_trade(&mut self, agents: &mut Agents, good: Good, ledger: &mut Ledger) -> UnexecutedTrades;
+
+    fn execute_trades(&mut self, agents: &mut Agents, ledger: &mut Ledger) -> GoodMap<UnexecutedTrades> {
+        self.goods().into_iter()
+            .map(|good| {
+                let span = debug_span!("good", %good);
+                let _enter = span.enter();
+                let unexecuted = self.execute_trade(agents, good, ledger);
                 self.update_price(unexecuted, good);
                 (good, unexecuted)
             })
             .collect()
     }
 
-    fn update_price(&mut self, ts: UnexecutedTrades, good: Good) -> i16;
+    fn update_price(&mut self, ts: UnexecutedTrades, good: Good) -> Money;
 
-    fn value(&self, good: Good, amt: i16) -> i16 {
-        self.price(good) * amt
+    /// Worth of `amt` units at the current price, errors on overflow
+    fn value(&self, good: Good, amt: Quantity) -> Result<Money, Error> {
+        self.price(good).checked_mul(amt)
     }
 
-    fn buy(&mut self, agent: &mut Agent, good: Good, amt: i16) -> Result<(), Error> {
-        self.trade((agent.cash, agent.id), good, amt)
+    /// Buy up to `amt` units for agent `id` right away, from the orders left standing after
+    /// the last round, at the prices they were placed at. Stops when the offers or the
+    /// buyer's cash run out.
+    /// By default nothing is left standing and nothing is filled.
+    fn buy(&mut self, _agents: &mut Agents, _id: AgentId, _good: Good, _amt: Quantity,
+           _ledger: &mut Ledger) -> Result<Fill, Error> {
+        Ok(Fill::default())
     }
 
-    fn sell(&mut self, agent: &mut Agent, good: Good, amt: i16) -> Result<(), Error> {
-        self.trade((agent.cash, agent.id), good, -amt)
+    /// Sell up to `amt` units for agent `id` right away, like `buy`
+    fn sell(&mut self, _agents: &mut Agents, _id: AgentId, _good: Good, _amt: Quantity,
+            _ledger: &mut Ledger) -> Result<Fill, Error> {
+        Ok(Fill::default())
     }
 }
 
+/// Result of an immediate `buy` or `sell`
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Fill {
+    pub qty: Quantity,
+    /// cash paid for a buy, received for a sell
+    pub value: Money,
+}
+
 
 pub struct ClearingMarket {
-    pub prices: GoodMap<(i16, i16, UnexecutedTrades)>,
-    pub trades: GoodMap<Vec<(AgentId, i16)>>,
+    pub prices: GoodMap<(Money, Money, UnexecutedTrades)>,
+    pub trades: GoodMap<Vec<(AgentId, Quantity)>>,
+    /// (price, buy units, sell units) left unexecuted by the last round, open to `buy` and
+    /// `sell` at the price the round cleared at until the next round executes
+    standing: GoodMap<(Money, Vec<AgentId>, Vec<AgentId>)>,
+    adjusters: GoodMap<Box<dyn PriceAdjuster>>,
+    rng: SmallRng,
 }
 
 impl ClearingMarket {
-    pub fn new(mut prices: HashMap<Good, i16>) -> ClearingMarket {
-        let trades = prices.iter().map(|(&k, _)| (k, Vec::new())).collect();
+    /// `rng` decides which buyers and sellers are matched when one side is rationed.
+    /// Prices start out adjusted by the `Adaptive` rule.
+    pub fn new(mut prices: HashMap<Good, Money>, rng: SmallRng) -> ClearingMarket {
+        let mut prices: Vec<_> = prices.drain().collect();
+        prices.sort();
+        let trades = prices.iter().map(|&(k, _)| (k, Vec::new())).collect();
+        let standing = prices.iter().map(|&(k, p)| (k, (p, Vec::new(), Vec::new()))).collect();
+        let adjusters = prices.iter()
+            .map(|&(k, _)| (k, Box::new(Adaptive) as Box<dyn PriceAdjuster>))
+            .collect();
         let prices = LinearMap::from_iter(prices
-            .drain()
-            .map(|(g, p)| (g, (p, p, All(0)))));
-        ClearingMarket { prices, trades }
+            .into_iter()
+            .map(|(g, p)| (g, (p, p, All(Quantity::ZERO, Quantity::ZERO)))));
+        ClearingMarket { prices, trades, standing, adjusters, rng }
     }
 
-    fn execute_transaction(&self, agents: &mut HashMap<AgentId, Agent>, buyer: AgentId, seller: AgentId, good: Good) {
-        let price = self.price(good);
+    /// Change the rule moving the price of `good` between rounds
+    pub fn set_adjuster(&mut self, good: Good, adjuster: Box<dyn PriceAdjuster>) {
+        *self.adjusters.get_mut(&good).unwrap() = adjuster;
+    }
+
+    /// Post a new price for `good`, the current one becomes the old price
+    pub fn set_price(&mut self, good: Good, price: Money) {
+        let (p0, _, unex) = self.prices[&good];
+        *self.prices.get_mut(&good).unwrap() = (price, p0, unex);
+    }
+
+    fn execute_transaction(&self, agents: &mut Agents, buyer: AgentId, seller: AgentId, good: Good,
+                           ledger: &mut Ledger) -> Result<(), Error> {
+        transfer(agents, buyer, seller, good, self.price(good), ledger)
+    }
+}
 
-        let buyer = agents.get_mut(&buyer).unwrap();
-        buyer.cash -= price;
-        *buyer.res.get_mut(&good).unwrap() += 1;
+/// Move one unit of `good` from seller to buyer and `price` cash the other way.
+/// Nothing changes if any of the new balances would overflow.
+pub(crate) fn transfer(agents: &mut Agents, buyer: AgentId, seller: AgentId, good: Good, price: Money,
+                       ledger: &mut Ledger) -> Result<(), Error> {
+    let one = Quantity(1);
+    let b = &agents[&buyer];
+    let (b_cash, b_res) = (b.cash.checked_sub(price)?, b.res[&good].checked_add(one)?);
+    let s = &agents[&seller];
+    let (s_cash, s_res) = (s.cash.checked_add(price)?, s.res[&good].checked_sub(one)?);
+
+    ledger.transfer(buyer, seller, good, one, price);
+
+    let b = agents.get_mut(&buyer).unwrap();
+    b.cash = b_cash;
+    b.res.insert(good, b_res);
+
+    let s = agents.get_mut(&seller).unwrap();
+    s.cash = s_cash;
+    s.res.insert(good, s_res);
+    Ok(())
+}
 
-        let seller = agents.get_mut(&seller).unwrap();
-        seller.cash += price;
-        *seller.res.get_mut(&good).unwrap() -= 1;
+/// Move `amt` cash from `payer` to `payee`.
+/// Nothing changes if either of the new balances would overflow.
+pub(crate) fn transfer_cash(agents: &mut Agents, payer: AgentId, payee: AgentId, amt: Money) -> Result<(), Error> {
+    if payer == payee {
+        return Ok(());
     }
+    let from = agents[&payer].cash.checked_sub(amt)?;
+    let to = agents[&payee].cash.checked_add(amt)?;
+    agents.get_mut(&payer).unwrap().cash = from;
+    agents.get_mut(&payee).unwrap().cash = to;
+    Ok(())
+}
+
+/// Trade up to `amt` units for `id` against `standing` units of the other side at `price`,
+/// last first. Standing units that can no longer be honoured are dropped.
+#[allow(clippy::too_many_arguments)]
+fn fill_standing(agents: &mut Agents, id: AgentId, good: Good, amt: Quantity, price: Money, buy: bool,
+                 standing: &mut Vec<AgentId>, ledger: &mut Ledger) -> Result<Fill, Error> {
+    let mut fill = Fill::default();
+    while fill.qty < amt {
+        let i = match standing.iter().rposition(|&a| a != id) {
+            Some(i) => i,
+            None => break,
+        };
+        let other = standing[i];
+        let (buyer, seller) = if buy { (id, other) } else { (other, id) };
+        // the other side may have died since the round
+        let can_pay = agents.get(&buyer).is_some_and(|a| a.cash >= price);
+        let can_deliver = agents.get(&seller).is_some_and(|a| a.res[&good] >= Quantity(1));
+        let (own_ok, other_ok) = if buy { (can_pay, can_deliver) } else { (can_deliver, can_pay) };
+        if !own_ok {
+            break;
+        }
+        if !other_ok {
+            reject(standing, other);
+            continue;
+        }
+        standing.remove(i);
+        transfer(agents, buyer, seller, good, price, ledger)?;
+        fill.qty += Quantity(1);
+        fill.value += price;
+    }
+    Ok(fill)
+}
+
+/// Drop every remaining unit of `agent`'s order, returns how many there were
+fn reject(units: &mut Vec<AgentId>, agent: AgentId) -> usize {
+    let before = units.len();
+    units.retain(|&a| a != agent);
+    before - units.len()
 }
 
-fn partition_and_shuffle_trades(trades: &mut Vec<(AgentId, i16)>) -> (Vec<AgentId>, Vec<AgentId>) {
-    type Trades = Vec<(AgentId, i16)>;
-    let mut rng = SmallRng::from_entropy();
-    let f = |pred: fn(i16) -> bool| {
+fn partition_and_shuffle_trades(trades: &mut Vec<(AgentId, Quantity)>, rng: &mut SmallRng) -> (Vec<AgentId>, Vec<AgentId>) {
+    let f = |pred: fn(Quantity) -> bool| {
         trades.iter()
             .filter(|x| pred(x.1))
-            .flat_map(|(a, x)| repeat(*a).take(x.abs() as usize))
+            .flat_map(|(a, x)| repeat_n(*a, x.abs().0 as usize))
             .collect::<Vec<_>>()
     };
 
-    let mut buys: Vec<AgentId> = f(|x| x > 0);
-    let mut sells: Vec<AgentId> = f(|x| x < 0);
-    buys.shuffle(&mut rng);
-    sells.shuffle(&mut rng);
+    let mut buys: Vec<AgentId> = f(|x| x > Quantity::ZERO);
+    let mut sells: Vec<AgentId> = f(|x| x < Quantity::ZERO);
+    buys.shuffle(rng);
+    sells.shuffle(rng);
     trades.clear();
     (buys, sells)
 }
 
+/// Outcome of a round for one good. The last field of each variant counts units
+/// rejected at execution because the buyer couldn't pay or the seller no longer held them,
+/// they are left out of the other counts.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum UnexecutedTrades {
-    Buys(i16, i16),
-    Sells(i16, i16),
-    All(i16),
+    /// (unexecuted buys, total buys, rejected)
+    Buys(Quantity, Quantity, Quantity),
+    /// (unexecuted sells, total sells, rejected)
+    Sells(Quantity, Quantity, Quantity),
+    /// (volume, rejected)
+    All(Quantity, Quantity),
+}
+
+impl UnexecutedTrades {
+    pub fn rejected(self) -> Quantity {
+        match self {
+            Buys(_, _, r) | Sells(_, _, r) | All(_, r) => r,
+        }
+    }
 }
 
 impl Market for ClearingMarket {
-    fn price(&self, good: Good) -> i16 {
+    fn goods(&self) -> Vec<Good> {
+        self.prices.keys().cloned().collect()
+    }
+
+    fn price(&self, good: Good) -> Money {
         self.prices[&good].0
     }
-    fn old_price(&self, good: Good) -> i16 {
+    fn old_price(&self, good: Good) -> Money {
         self.prices[&good].1
     }
 
-    fn trade(&mut self, (cash, id): (i16, u16), good: Good, amt: i16) -> Result<(), Error> {
-        if amt > 0 && cash < self.value(good, amt) {
+    fn trade(&mut self, (cash, id): (Money, AgentId), good: Good, amt: Quantity) -> Result<(), Error> {
+        if amt > Quantity::ZERO && cash < self.value(good, amt)? {
             Err(failure::err_msg("insufficient cash to make trade"))
         } else {
-            Ok(self.trades.get_mut(&good).unwrap().push((id, amt)))
+            self.trades.get_mut(&good).unwrap().push((id, amt));
+            Ok(())
         }
     }
 
-    fn execute_trade(&mut self, agents: &mut HashMap<AgentId, Agent>, good: Good) -> UnexecutedTrades {
+    /// Matches random buy and sell units at the posted price. Each match is checked against
+    /// the agents as they are now: a buyer who can't pay or a seller who has run out has
+    /// the rest of its order rejected, and its counterpart waits for the next match.
+    fn execute_trade(&mut self, agents: &mut Agents, good: Good, ledger: &mut Ledger) -> UnexecutedTrades {
+        let price = self.price(good);
         let trades = self.trades
             .get_mut(&good)
             .unwrap();
-        let (mut buys, mut sells) = partition_and_shuffle_trades(trades);
-        println!("execute trade: trades, buys, sells, {:?}, {:?}, {:?}", &trades, &buys, &sells);
-//        let total_trades = buys.len() + sells.len();
+        let (mut buys, mut sells) = partition_and_shuffle_trades(trades, &mut self.rng);
+        trace!(%good, ?buys, ?sells, "execute trade");
         let (total_sells, total_buys) = (sells.len(), buys.len());
-        dbg!(total_sells, total_buys);
-
-        let num_trades = buys.len().min(sells.len());
-        for _ in 0..num_trades {
-            let b = buys.pop();
-            let s = sells.pop();
-            match (b, s) {
-                (Some(b), Some(s)) => {
-                    self.execute_transaction(agents, b, s, good);
+        let (mut rejected_buys, mut rejected_sells, mut volume) = (0, 0, 0);
+
+        while let (Some(&b), Some(&s)) = (buys.last(), sells.last()) {
+            if agents[&b].cash < price {
+                trace!(agent = b, cash = %agents[&b].cash, %price, "buyer can't pay");
+                rejected_buys += reject(&mut buys, b);
+            } else if agents[&s].res[&good] < Quantity(1) {
+                trace!(agent = s, "seller has nothing left");
+                rejected_sells += reject(&mut sells, s);
+            } else {
+                buys.pop();
+                sells.pop();
+                match self.execute_transaction(agents, b, s, good, ledger) {
+                    Ok(()) => volume += 1,
+                    Err(e) => error!(buyer = b, seller = s, "trade not executed: {}", e),
                 }
-//                (None, Some(s)) => sells.push(s),
-//                (Some(b), None) => buys.push(b),
-                _ => eprintln!("Should never happen")
             }
         }
 
-        dbg!(buys.len(), sells.len());
-        match (buys.len(), sells.len()) {
-            (0, 0) => All(((total_buys + total_sells) / 2) as i16),
-            (0, x) => Sells(x as i16, total_sells as i16),
-            (x, 0) => Buys(x as i16, total_buys as i16),
+        trace!(%good, total_buys, total_sells, unexecuted_buys = buys.len(), unexecuted_sells = sells.len(),
+               rejected_buys, rejected_sells, "executed");
+        let units = |n: usize| Quantity(n as i32);
+        let rejected = units(rejected_buys + rejected_sells);
+        let (unexecuted_buys, unexecuted_sells) = (buys.len(), sells.len());
+        *self.standing.get_mut(&good).unwrap() = (price, buys, sells);
+        match (unexecuted_buys, unexecuted_sells) {
+            (0, 0) => All(units(volume), rejected),
+            (0, x) => Sells(units(x), units(total_sells - rejected_sells), rejected),
+            (x, 0) => Buys(units(x), units(total_buys - rejected_buys), rejected),
             (x, y) => {
-                eprintln!("Shouldn't happen {}, {}", x, y);
-                Sells(0, 0)
+                error!("Shouldn't happen {}, {}", x, y);
+                Sells(Quantity::ZERO, Quantity::ZERO, rejected)
             }
         }
     }
 
-    fn update_price(&mut self, ts: UnexecutedTrades, good: Good) -> i16 {
-        let (p0, p1, old_unex) = self.prices[&good];
-        let (p0, p1) = (p0 as f64, p1 as f64);
-        let dp = p0 - p1; // dp > 0 if price increased
-        let p_new = match ts {
-            All(_) => p0,
-            Buys(_, _) | Sells(_, _) => {
-                match old_unex {
-                    All(_) => p0 * (1. + 0.25 * unex_ratio(ts)),
-                    old => {
-                        let r0 = unex_ratio(ts);
-                        let r1 = unex_ratio(old);
-                        if r0 * r1 < 0. { // if the sell -> buy or buy -> sell...
-                            p0 + dp * 0.5 * r0 / r1
-                        } else if dp.abs() > 0.5 {
-                            p0 + dp * 1.5 * r0 / r1
-                        } else {
-                            p0 + 2. * r0 / r1.abs()
-                        }
-                    }
-                }
-            }
-        }.round().max(0.) as i16;
-        //dbg!(p_new, p0, ts);
-//        match ts {
-//            All(_) => assert_eq!(p_new, p),
-//            Buys(unexecuted, executed) => {
-//                assert!(p_new > p)
-//            }
-//            Sells(unexecuted, executed) => {
-//                assert!(p_new < p)
-//            }
-//        }
-        *self.prices.get_mut(&good).unwrap() = (p_new, p0.round() as i16, ts);
-        p_new
+    /// Takes units the last round couldn't match, at the price it was executed at rather
+    /// than the one posted after it, which neither side agreed to
+    fn buy(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity,
+           ledger: &mut Ledger) -> Result<Fill, Error> {
+        let (price, _, sells) = self.standing.get_mut(&good).unwrap();
+        fill_standing(agents, id, good, amt, *price, true, sells, ledger)
+    }
+
+    fn sell(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity,
+            ledger: &mut Ledger) -> Result<Fill, Error> {
+        let (price, buys, _) = self.standing.get_mut(&good).unwrap();
+        fill_standing(agents, id, good, amt, *price, false, buys, ledger)
     }
-}
 
-fn unex_ratio(a: UnexecutedTrades) -> f64 {
-    match a {
-        Buys(u0, e0) => u0 as f64 / e0 as f64,
-        Sells(u0, e0) => -u0 as f64 / e0 as f64,
-        All(_) => 1.
+    fn update_price(&mut self, ts: UnexecutedTrades, good: Good) -> Money {
+        let (p0, p1, old_unex) = self.prices[&good];
+        let p_new = self.adjusters.get_mut(&good).unwrap().adjust(p0, p1, old_unex, ts);
+        *self.prices.get_mut(&good).unwrap() = (p_new, p0, ts);
+        p_new
     }
 }
 
@@ -210,63 +342,162 @@ fn unex_ratio(a: UnexecutedTrades) -> f64 {
 mod tests {
     use maplit::hashmap;
 
-    use crate::goods::Good::{Food, Grain};
+    use crate::goods::Goods;
+    use crate::testing::{goods, market};
 
     use super::*;
 
+    fn food_and_grain() -> (Goods, Good, Good) {
+        let (goods, [food, grain]) = goods(["Food", "Grain"]);
+        (goods, food, grain)
+    }
+
     #[test]
     fn hi() {
-        let mut agents = Agent::pre_made(2);
-        let mut market = ClearingMarket::new(hashmap! { Food => 20, Grain => 20, });
-        let keys: Vec<_> = agents.keys().into_iter().collect();
+        let (goods, food, grain) = food_and_grain();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut agents = Agent::pre_made(2, &goods, &mut rng);
+        let mut market = ClearingMarket::new(hashmap! { food => Money(20), grain => Money(20), }, rng);
+        let keys: Vec<_> = agents.keys().collect();
         let b = *keys[0];
         let s = *keys[1];
 
-        market.trade((agents[&b].cash, agents[&b].id), Food, 2).unwrap();
-        market.trade((agents[&s].cash, agents[&s].id), Food, -2).unwrap();
+        market.trade((agents[&b].cash, agents[&b].id), food, Quantity(2)).unwrap();
+        market.trade((agents[&s].cash, agents[&s].id), food, Quantity(-2)).unwrap();
 
-        let b_f = agents[&b].res[&Food];
-        let s_f = agents[&s].res[&Food];
-        assert_eq!(market.trades[&Food], vec![(b, 2), (s, -2)]);
+        let b_f = agents[&b].res[&food];
+        let s_f = agents[&s].res[&food];
+        assert_eq!(market.trades[&food], vec![(b, Quantity(2)), (s, Quantity(-2))]);
 
-        let rem = market.execute_trade(&mut agents, Food);
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
 
-        assert_eq!(rem, All(2));
-        assert_eq!(agents[&b].res[&Food], b_f + 2);
-        assert_eq!(agents[&s].res[&Food], s_f - 2);
+        assert_eq!(rem, All(Quantity(2), Quantity(0)));
+        assert_eq!(agents[&b].res[&food], b_f + Quantity(2));
+        assert_eq!(agents[&s].res[&food], s_f - Quantity(2));
 
-        let p = market.price(Food);
-        let p1 = market.update_price(rem, Food);
+        let p = market.price(food);
+        let p1 = market.update_price(rem, food);
         assert_eq!(p1, p);
     }
 
     #[test]
     fn buy_heavy() {
-        let mut agents = Agent::pre_made(3);
-        let mut market = ClearingMarket::new(hashmap! { Food => 20, Grain => 20, });
-        let keys: Vec<_> = agents.keys().into_iter().collect();
+        let (goods, food, grain) = food_and_grain();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut agents = Agent::pre_made(3, &goods, &mut rng);
+        let mut market = ClearingMarket::new(hashmap! { food => Money(20), grain => Money(20), }, rng);
+        let keys: Vec<_> = agents.keys().collect();
         let b = *keys[0];
         let b1 = *keys[1];
         let s = *keys[2];
 
-        market.trade((agents[&b].cash, agents[&b].id), Food, 2).unwrap();
-        market.trade((agents[&b1].cash, agents[&b1].id), Food, 2).unwrap();
-        market.trade((agents[&s].cash, agents[&s].id), Food, -2).unwrap();
+        market.trade((agents[&b].cash, agents[&b].id), food, Quantity(2)).unwrap();
+        market.trade((agents[&b1].cash, agents[&b1].id), food, Quantity(2)).unwrap();
+        market.trade((agents[&s].cash, agents[&s].id), food, Quantity(-2)).unwrap();
+
+        let b_f = agents[&b].res[&food];
+        let b1_f = agents[&b1].res[&food];
+        let s_f = agents[&s].res[&food];
+        assert_eq!(market.trades[&food], vec![(b, Quantity(2)), (b1, Quantity(2)), (s, Quantity(-2))]);
 
-        let b_f = agents[&b].res[&Food];
-        let b1_f = agents[&b1].res[&Food];
-        let s_f = agents[&s].res[&Food];
-        assert_eq!(market.trades[&Food], vec![(b, 2), (b1, 2), (s, -2)]);
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
 
-        let rem = market.execute_trade(&mut agents, Food);
+        assert_eq!(rem, Buys(Quantity(2), Quantity(4), Quantity(0)));
+        assert_eq!(agents[&b].res[&food] + agents[&b1].res[&food], b1_f + b_f + Quantity(2));
+        assert_eq!(agents[&s].res[&food], s_f - Quantity(2));
 
-        assert_eq!(rem, Buys(2, 4));
-        assert_eq!(agents[&b].res[&Food] + agents[&b1].res[&Food], b1_f + b_f + 2);
-        assert_eq!(agents[&s].res[&Food], s_f - 2);
+        let p = market.price(food);
+        let p1 = market.update_price(rem, food);
+        assert_eq!(p1, p.scale(1.125));
+    }
 
-        let p = market.price(Food);
-        let p1 = market.update_price(rem, Food);
-        assert_eq!(p1, (p as f64 * 1.125).round() as i16);
+    #[test]
+    fn infeasible_units_rejected() {
+        let (_, food, _) = food_and_grain();
+        let mut agents = Agents::new();
+        for _ in 0..3 {
+            Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(1)}, hashmap! {food => 1.0}).unwrap();
+        }
+        let mut market = market(hashmap! { food => Money(20) });
+        market.trade((agents[&0].cash, 0), food, Quantity(3)).unwrap();
+        // agent 1 offers more than it holds
+        market.trade((agents[&1].cash, 1), food, Quantity(-3)).unwrap();
+        market.trade((agents[&2].cash, 2), food, Quantity(-1)).unwrap();
+
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+
+        assert_eq!(rem, Buys(Quantity(1), Quantity(3), Quantity(2)));
+        assert_eq!(agents[&0].res[&food], Quantity(3));
+        assert_eq!(agents[&0].cash, Money(60));
+        assert_eq!(agents[&1].res[&food], Quantity(0));
+        assert_eq!(agents[&2].res[&food], Quantity(0));
+    }
+
+    #[test]
+    fn buy_from_standing_sells() {
+        let (_, food, _) = food_and_grain();
+        let mut agents = Agents::new();
+        for _ in 0..3 {
+            Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(5)}, hashmap! {food => 1.0}).unwrap();
+        }
+        let mut market = market(hashmap! { food => Money(20) });
+        let mut ledger = Ledger::default();
+        market.trade((agents[&1].cash, 1), food, Quantity(-3)).unwrap();
+        market.trade((agents[&2].cash, 2), food, Quantity(-1)).unwrap();
+        let rem = market.execute_trade(&mut agents, food, &mut ledger);
+        assert_eq!(rem, Sells(Quantity(4), Quantity(4), Quantity(0)));
+
+        let fill = market.buy(&mut agents, 0, food, Quantity(2), &mut ledger).unwrap();
+        assert_eq!(fill, Fill { qty: Quantity(2), value: Money(40) });
+        assert_eq!(agents[&0].res[&food], Quantity(7));
+        assert_eq!(agents[&0].cash, Money(60));
+
+        // stops when the buyer can't pay for the next unit
+        agents.get_mut(&0).unwrap().cash = Money(30);
+        let fill = market.buy(&mut agents, 0, food, Quantity(5), &mut ledger).unwrap();
+        assert_eq!(fill.qty, Quantity(1));
+        assert_eq!(ledger.len(), 3);
+    }
+
+    #[test]
+    fn standing_units_fill_at_the_round_price() {
+        let (_, food, _) = food_and_grain();
+        let mut agents = Agents::new();
+        for _ in 0..3 {
+            Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(5)}, hashmap! {food => 1.0}).unwrap();
+        }
+        let mut market = market(hashmap! { food => Money(20) });
+        let mut ledger = Ledger::default();
+        market.trade((agents[&1].cash, 1), food, Quantity(-3)).unwrap();
+        market.trade((agents[&2].cash, 2), food, Quantity(-1)).unwrap();
+        let rem = market.execute_trades(&mut agents, &mut ledger);
+        assert!(matches!(rem[&food], Sells(..)));
+        // excess supply has lowered the posted price, the sellers offered at 20
+        assert!(market.price(food) < Money(20));
+
+        let fill = market.buy(&mut agents, 0, food, Quantity(2), &mut ledger).unwrap();
+        assert_eq!(fill, Fill { qty: Quantity(2), value: Money(40) });
+        assert_eq!(agents[&0].cash, Money(60));
+    }
+
+    #[test]
+    fn value_overflow() {
+        let (_, food, grain) = food_and_grain();
+        let mut market = market(hashmap! { food => Money(25), grain => Money::MAX, });
+        assert_eq!(market.value(food, Quantity(40)).unwrap(), Money(1000));
+        assert_eq!(market.value(food, Quantity(2000)).unwrap(), Money(50_000));
+        assert!(market.value(grain, Quantity(2)).is_err());
+        assert!(market.trade((Money(500), 0), grain, Quantity(2)).is_err());
+        assert!(market.values(&[(food, Quantity(1)), (grain, Quantity(1))]).is_err());
+
+        // a payment that would overflow is refused, nobody's cash changes
+        let mut agents = Agents::new();
+        Agent::new_into_map(&mut agents, Money(10), hashmap! {food => Quantity(0)}, hashmap! {food => 1.0}).unwrap();
+        Agent::new_into_map(&mut agents, Money::MAX, hashmap! {food => Quantity(0)}, hashmap! {food => 1.0}).unwrap();
+        assert!(transfer_cash(&mut agents, 0, 1, Money(5)).is_err());
+        assert_eq!((agents[&0].cash, agents[&1].cash), (Money(10), Money::MAX));
+        transfer_cash(&mut agents, 1, 0, Money(5)).unwrap();
+        assert_eq!(agents[&0].cash, Money(15));
     }
 }
 
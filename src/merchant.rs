@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use crate::agent::{Agent, MU};
+use crate::brain::Brain;
+use crate::goods::{Good, Task};
+use crate::market::Market;
+use crate::units::{Money, Quantity};
+
+/// Intermediary that neither eats nor produces. It holds stock and cash, buys below and
+/// sells above its running estimate of each good's price. Away from target inventory both
+/// quotes shift towards getting back to it and the side that would move it further widens.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Merchant {
+    /// half the bid-ask spread at target inventory, as a fraction of the price
+    pub spread: f64,
+    /// how much being away from target inventory shifts and widens the quotes
+    pub risk: f64,
+    /// most units bought or sold per good and round
+    pub depth: Quantity,
+    /// weight of the newest price in the fair price estimate
+    pub smoothing: f64,
+    /// inventory aimed for in each good dealt in
+    pub target: HashMap<Good, Quantity>,
+    /// running estimate of each good's price
+    pub fair: HashMap<Good, Money>,
+}
+
+impl Merchant {
+    /// Deals in the goods in `target`, with `fair` its starting price estimates
+    pub fn new(spread: f64, risk: f64, depth: Quantity, smoothing: f64,
+               target: HashMap<Good, Quantity>, fair: HashMap<Good, Money>) -> Merchant {
+        Merchant { spread, risk, depth, smoothing, target, fair }
+    }
+
+    /// (bid, ask) for `good` holding `stock`, none for goods it doesn't deal in
+    pub fn quotes(&self, good: Good, stock: Quantity) -> Option<(Money, Money)> {
+        let fair = *self.fair.get(&good)?;
+        let target = *self.target.get(&good)?;
+        // relative distance from target, positive when overstocked
+        let dev = (stock.0 - target.0) as f64 / target.0.max(1) as f64;
+        let mid = (fair.0 as f64 * (1. - self.risk * self.spread * dev)).max(0.);
+        let bid_half = self.spread * (1. + self.risk * dev.max(0.));
+        let ask_half = self.spread * (1. + self.risk * (-dev).max(0.));
+        let bid = Money((mid * (1. - bid_half)).round().max(0.) as i64);
+        let ask = Money((mid * (1. + ask_half)).round() as i64).max(bid + Money(1));
+        Some((bid, ask))
+    }
+
+    /// Units bought (positive) or sold (negative) at a posted `price`: up to `depth` at or
+    /// below the bid, as many as cash allows, and up to `depth` at or above the ask
+    pub fn demand(&self, good: Good, price: Money, cash: Money, stock: Quantity) -> Quantity {
+        match self.quotes(good, stock) {
+            Some((bid, _)) if price <= bid => {
+                let affordable = if price > Money::ZERO { cash.0 / price.0 } else { self.depth.0 as i64 };
+                Quantity(affordable.min(self.depth.0 as i64).max(0) as i32)
+            }
+            Some((_, ask)) if price >= ask => -stock.max(Quantity::ZERO).min(self.depth),
+            _ => Quantity::ZERO,
+        }
+    }
+
+    /// Limit prices per unit: `depth` bids at the bid as far as cash goes,
+    /// `depth` asks at the ask as far as stock goes
+    pub fn reservation_prices(&self, good: Good, cash: Money, stock: Quantity) -> (Vec<Money>, Vec<Money>) {
+        match self.quotes(good, stock) {
+            Some((bid, ask)) => {
+                let bids = if bid > Money::ZERO { (cash.0 / bid.0).min(self.depth.0 as i64).max(0) } else { 0 };
+                let asks = stock.min(self.depth).0.max(0);
+                (vec![bid; bids as usize], vec![ask; asks as usize])
+            }
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Move the fair price of `good` towards the market's `price`
+    pub fn observe(&mut self, good: Good, price: Money) {
+        if let Some(fair) = self.fair.get_mut(&good) {
+            *fair = Money((fair.0 as f64 * (1. - self.smoothing) + price.0 as f64 * self.smoothing).round() as i64);
+        }
+    }
+}
+
+impl Brain for Merchant {
+    fn demand(&self, a: &Agent, price: Money, _mu: &MU, good: Good) -> Quantity {
+        Merchant::demand(self, good, price, a.cash, a.res[&good])
+    }
+
+    fn reservation_prices(&self, a: &Agent, _mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        Merchant::reservation_prices(self, good, a.cash, a.res[&good])
+    }
+
+    fn choose_task<'a>(&self, _a: &Agent, _tasks: &'a [Task], _market: &dyn Market) -> Option<&'a Task> {
+        None
+    }
+
+    fn consume(&self, _a: &Agent, _food: Good, _mu: &MU, _max: Quantity) -> Option<Quantity> {
+        None
+    }
+
+    fn observe(&mut self, good: Good, price: Money) {
+        Merchant::observe(self, good, price)
+    }
+
+    fn employs(&self, _task: &Task) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::testing::goods;
+
+    use super::*;
+
+    fn food_merchant() -> (Merchant, Good) {
+        let (_, [food]) = goods(["Food"]);
+        let m = Merchant::new(0.1, 1.0, Quantity(5), 0.5,
+                              hashmap! {food => Quantity(20)}, hashmap! {food => Money(100)});
+        (m, food)
+    }
+
+    #[test]
+    fn inventory_skews_quotes() {
+        let (m, food) = food_merchant();
+        assert_eq!(m.quotes(food, Quantity(20)), Some((Money(90), Money(110))));
+
+        // overstocked: cheaper, and reluctant to buy more
+        assert_eq!(m.quotes(food, Quantity(30)), Some((Money(81), Money(105))));
+        // understocked: dearer, and reluctant to sell
+        assert_eq!(m.quotes(food, Quantity(10)), Some((Money(95), Money(121))));
+    }
+
+    #[test]
+    fn absorbs_imbalances() {
+        let (mut m, food) = food_merchant();
+        assert_eq!(m.demand(food, Money(80), Money(1000), Quantity(20)), Quantity(5));
+        // limited by cash
+        assert_eq!(m.demand(food, Money(80), Money(200), Quantity(20)), Quantity(2));
+        assert_eq!(m.demand(food, Money(100), Money(1000), Quantity(20)), Quantity(0));
+        assert_eq!(m.demand(food, Money(130), Money(1000), Quantity(3)), Quantity(-3));
+
+        assert_eq!(m.reservation_prices(food, Money(200), Quantity(20)),
+                   (vec![Money(90); 2], vec![Money(110); 5]));
+
+        m.observe(food, Money(60));
+        assert_eq!(m.fair[&food], Money(80));
+    }
+}
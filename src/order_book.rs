@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::iter::FromIterator;
+
+use failure::Error;
+use linear_map::LinearMap;
+use rand::prelude::{SliceRandom, SmallRng};
+use tracing::{error, trace, trace_span};
+
+use crate::agent::{AgentId, Agents, MU};
+use crate::goods::Good;
+use crate::ledger::Ledger;
+use crate::market::{transfer, Fill, GoodMap, Market, UnexecutedTrades};
+use crate::market::UnexecutedTrades::{All, Buys, Sells};
+use crate::units::{Money, Quantity};
+
+/// Continuous double auction. Agents post limit bids and asks at their reservation prices,
+/// each arriving order trades against the resting book by price-time priority at the
+/// resting order's price and the rest of it joins the book.
+/// Books are cleared before every round, agents re-quote from their new holdings.
+/// Until then what is left in the book can be taken by `buy` and `sell`.
+pub struct OrderBookMarket {
+    /// (price, old price), price is the volume weighted average of the last round with trades
+    pub prices: GoodMap<(Money, Money)>,
+    /// orders in arrival order with their limit, none for market orders, submitted to the
+    /// book when the round executes
+    pub incoming: GoodMap<Vec<(AgentId, Quantity, Option<Money>)>>,
+    last_round: GoodMap<RoundStats>,
+    /// resting orders left after the last round
+    standing: GoodMap<OrderBook>,
+    rng: SmallRng,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Order {
+    pub agent: AgentId,
+    pub price: Money,
+    pub qty: Quantity,
+}
+
+/// Resting orders, best first
+#[derive(Debug, Default)]
+pub struct OrderBook {
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RoundStats {
+    volume: Quantity,
+    value: Money,
+    best_bid: Option<Money>,
+    best_ask: Option<Money>,
+    /// units dropped because the buyer couldn't pay or the seller ran out
+    rejected_bids: Quantity,
+    rejected_asks: Quantity,
+}
+
+impl OrderBookMarket {
+    /// `rng` decides the order agents' quotes arrive in
+    pub fn new(mut prices: HashMap<Good, Money>, rng: SmallRng) -> OrderBookMarket {
+        let mut prices: Vec<_> = prices.drain().collect();
+        prices.sort();
+        let incoming = prices.iter().map(|&(g, _)| (g, Vec::new())).collect();
+        let last_round = prices.iter().map(|&(g, _)| (g, RoundStats::default())).collect();
+        let standing = prices.iter().map(|&(g, _)| (g, OrderBook::default())).collect();
+        let prices = LinearMap::from_iter(prices.into_iter().map(|(g, p)| (g, (p, p))));
+        OrderBookMarket { prices, incoming, last_round, standing, rng }
+    }
+
+    /// Queue a limit order, positive `amt` buys and negative sells
+    pub fn limit_order(&mut self, id: AgentId, good: Good, amt: Quantity, limit: Money) {
+        if amt != Quantity::ZERO {
+            self.incoming.get_mut(&good).unwrap().push((id, amt, Some(limit)));
+        }
+    }
+
+    /// Queue a market order, positive `amt` buys and negative sells
+    pub fn market_order(&mut self, id: AgentId, good: Good, amt: Quantity) {
+        if amt != Quantity::ZERO {
+            self.incoming.get_mut(&good).unwrap().push((id, amt, None));
+        }
+    }
+}
+
+impl OrderBook {
+    /// Match an incoming order against the opposite side, then rest whatever is left if `rest`.
+    /// Units that the buyer can't pay for or the seller doesn't hold are not traded:
+    /// an infeasible incoming order is dropped, an infeasible resting order is cancelled.
+    #[allow(clippy::too_many_arguments)]
+    fn submit(&mut self, agents: &mut Agents, good: Good, order: Order, buy: bool, rest: bool,
+              stats: &mut RoundStats, ledger: &mut Ledger) {
+        let mut left = order.qty;
+        let opposite = if buy { &mut self.asks } else { &mut self.bids };
+        let mut i = 0;
+        let (none, one) = (Quantity::ZERO, Quantity(1));
+        while left > none && i < opposite.len() {
+            let resting = &mut opposite[i];
+            let crosses = if buy { resting.price <= order.price } else { resting.price >= order.price };
+            if !crosses {
+                break;
+            }
+            if resting.agent == order.agent {
+                i += 1;
+                continue;
+            }
+            let (buyer, seller) = if buy { (order.agent, resting.agent) } else { (resting.agent, order.agent) };
+            while left > none && resting.qty > none {
+                if !can_trade(agents, buyer, seller, good, resting.price) {
+                    break;
+                }
+                if let Err(e) = transfer(agents, buyer, seller, good, resting.price, ledger) {
+                    error!(buyer, seller, "trade not executed: {}", e);
+                    break;
+                }
+                trace!(buyer, seller, price = %resting.price, "matched");
+                stats.volume += one;
+                stats.value += resting.price;
+                left -= one;
+                resting.qty -= one;
+            }
+            if left > none && resting.qty > none {
+                let incoming_ok = if buy {
+                    agents[&buyer].cash >= resting.price
+                } else {
+                    agents[&seller].res[&good] > none
+                };
+                let (incoming_rejected, resting_rejected) = if buy {
+                    (&mut stats.rejected_bids, &mut stats.rejected_asks)
+                } else {
+                    (&mut stats.rejected_asks, &mut stats.rejected_bids)
+                };
+                if !incoming_ok {
+                    *incoming_rejected += left;
+                    left = none;
+                    break;
+                }
+                *resting_rejected += resting.qty;
+                resting.qty = none;
+            }
+            if resting.qty == none {
+                opposite.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        if rest && left > none {
+            let own = if buy { &mut self.bids } else { &mut self.asks };
+            // behind every order at the same or a better price
+            let at = own.iter()
+                .position(|o| if buy { o.price < order.price } else { o.price > order.price })
+                .unwrap_or(own.len());
+            own.insert(at, Order { qty: left, ..order });
+        }
+    }
+
+    /// Trade up to `amt` units for `id` against the resting orders, best first, at their prices.
+    /// Nothing is rested, resting orders that can no longer be honoured are cancelled.
+    fn take(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity, buy: bool,
+            ledger: &mut Ledger) -> Result<Fill, Error> {
+        let mut fill = Fill::default();
+        let opposite = if buy { &mut self.asks } else { &mut self.bids };
+        let mut i = 0;
+        while fill.qty < amt && i < opposite.len() {
+            let resting = &mut opposite[i];
+            if resting.agent == id {
+                i += 1;
+                continue;
+            }
+            let (buyer, seller) = if buy { (id, resting.agent) } else { (resting.agent, id) };
+            // the resting side may have died since the round
+            let can_pay = agents.get(&buyer).is_some_and(|a| a.cash >= resting.price);
+            let can_deliver = agents.get(&seller).is_some_and(|a| a.res[&good] > Quantity::ZERO);
+            let (own_ok, resting_ok) = if buy { (can_pay, can_deliver) } else { (can_deliver, can_pay) };
+            if !own_ok {
+                break;
+            }
+            if resting_ok {
+                transfer(agents, buyer, seller, good, resting.price, ledger)?;
+                fill.qty += Quantity(1);
+                fill.value += resting.price;
+                resting.qty -= Quantity(1);
+            } else {
+                resting.qty = Quantity::ZERO;
+            }
+            if resting.qty == Quantity::ZERO {
+                opposite.remove(i);
+            }
+        }
+        Ok(fill)
+    }
+}
+
+fn can_trade(agents: &Agents, buyer: AgentId, seller: AgentId, good: Good, price: Money) -> bool {
+    agents[&buyer].cash >= price && agents[&seller].res[&good] > Quantity::ZERO
+}
+
+/// Collapse runs of equal prices into single orders
+fn aggregate(prices: &[Money]) -> Vec<(Money, Quantity)> {
+    let mut orders: Vec<(Money, Quantity)> = Vec::new();
+    for &p in prices {
+        match orders.last_mut() {
+            Some((price, qty)) if *price == p => *qty += Quantity(1),
+            _ => orders.push((p, Quantity(1))),
+        }
+    }
+    orders
+}
+
+impl Market for OrderBookMarket {
+    fn goods(&self) -> Vec<Good> {
+        self.prices.keys().cloned().collect()
+    }
+
+    fn price(&self, good: Good) -> Money {
+        self.prices[&good].0
+    }
+
+    fn old_price(&self, good: Good) -> Money {
+        self.prices[&good].1
+    }
+
+    /// A market order, it trades at whatever the book offers and the rest is dropped
+    fn trade(&mut self, (cash, id): (Money, AgentId), good: Good, amt: Quantity) -> Result<(), Error> {
+        if amt > Quantity::ZERO && cash < self.value(good, amt)? {
+            Err(failure::err_msg("insufficient cash to make trade"))
+        } else {
+            self.market_order(id, good, amt);
+            Ok(())
+        }
+    }
+
+    fn collect_orders(&mut self, agents: &Agents, good: Good, mu: &MU) {
+        let mut ids: Vec<AgentId> = agents.keys().cloned().collect();
+        ids.shuffle(&mut self.rng);
+        for id in ids {
+            let span = trace_span!("agent", id);
+            let _enter = span.enter();
+            let (bids, asks) = agents[&id].reservation_prices(mu, good);
+            trace!(?bids, ?asks, "quotes");
+            for (price, qty) in aggregate(&bids) {
+                self.limit_order(id, good, qty, price);
+            }
+            for (price, qty) in aggregate(&asks) {
+                self.limit_order(id, good, -qty, price);
+            }
+        }
+    }
+
+    fn execute_trade(&mut self, agents: &mut Agents, good: Good, ledger: &mut Ledger) -> UnexecutedTrades {
+        let incoming = std::mem::take(self.incoming.get_mut(&good).unwrap());
+        let mut book = OrderBook::default();
+        let mut stats = RoundStats::default();
+        let (mut bid_units, mut ask_units) = (Quantity::ZERO, Quantity::ZERO);
+
+        for &(agent, amt, limit) in &incoming {
+            let buy = amt > Quantity::ZERO;
+            // market orders take any price and never rest
+            let price = limit.unwrap_or(if buy { Money::MAX } else { Money::ZERO });
+            let order = Order { agent, price, qty: amt.abs() };
+            if buy {
+                bid_units += amt;
+            } else {
+                ask_units -= amt;
+            }
+            book.submit(agents, good, order, buy, limit.is_some(), &mut stats, ledger);
+        }
+
+        stats.best_bid = book.bids.first().map(|o| o.price);
+        stats.best_ask = book.asks.first().map(|o| o.price);
+        *self.last_round.get_mut(&good).unwrap() = stats;
+
+        let unfilled = |orders: &[Order]| orders.iter().map(|o| o.qty).sum::<Quantity>();
+        let (bids_left, asks_left) = (unfilled(&book.bids), unfilled(&book.asks));
+        *self.standing.get_mut(&good).unwrap() = book;
+        let rejected = stats.rejected_bids + stats.rejected_asks;
+        trace!(volume = %stats.volume, %bids_left, %asks_left, %rejected, "book closed");
+        if bids_left > asks_left {
+            Buys(bids_left, bid_units - stats.rejected_bids, rejected)
+        } else if asks_left > bids_left {
+            Sells(asks_left, ask_units - stats.rejected_asks, rejected)
+        } else {
+            All(stats.volume, rejected)
+        }
+    }
+
+    /// Takes the asks left in the book, lowest first
+    fn buy(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity,
+           ledger: &mut Ledger) -> Result<Fill, Error> {
+        self.standing.get_mut(&good).unwrap().take(agents, id, good, amt, true, ledger)
+    }
+
+    /// Hits the bids left in the book, highest first
+    fn sell(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity,
+            ledger: &mut Ledger) -> Result<Fill, Error> {
+        self.standing.get_mut(&good).unwrap().take(agents, id, good, amt, false, ledger)
+    }
+
+    /// Price follows trades: the average traded price, else the middle of the spread left
+    /// in the book, else it stays put
+    fn update_price(&mut self, _ts: UnexecutedTrades, good: Good) -> Money {
+        let stats = self.last_round[&good];
+        let (p0, _) = self.prices[&good];
+        let p_new = if stats.volume > Quantity::ZERO {
+            Money((stats.value.0 as f64 / stats.volume.0 as f64).round() as i64)
+        } else {
+            match (stats.best_bid, stats.best_ask) {
+                (Some(b), Some(a)) => Money(((b.0 as i128 + a.0 as i128) / 2) as i64),
+                _ => p0,
+            }
+        };
+        *self.prices.get_mut(&good).unwrap() = (p_new, p0);
+        p_new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use rand::SeedableRng;
+
+    use crate::agent::Agent;
+    use crate::testing::goods;
+
+    use super::*;
+
+    fn setup() -> (Agents, OrderBookMarket, Good) {
+        let (_, [food]) = goods(["Food"]);
+        let mut agents = Agents::new();
+        for _ in 0..3 {
+            Agent::new_into_map(&mut agents, Money(100), hashmap! {food => Quantity(10)}, hashmap! {food => 1.0}).unwrap();
+        }
+        let market = OrderBookMarket::new(hashmap! {food => Money(20)}, SmallRng::seed_from_u64(0));
+        (agents, market, food)
+    }
+
+    #[test]
+    fn price_time_priority() {
+        let (mut agents, mut market, food) = setup();
+        // two asks at the same price, the earlier one fills first
+        market.limit_order(0, food, Quantity(-2), Money(15));
+        market.limit_order(1, food, Quantity(-2), Money(15));
+        market.limit_order(2, food, Quantity(3), Money(18));
+
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+
+        assert_eq!(agents[&0].res[&food], Quantity(8));
+        assert_eq!(agents[&1].res[&food], Quantity(9));
+        assert_eq!(agents[&2].res[&food], Quantity(13));
+        // trades at the resting price
+        assert_eq!(agents[&2].cash, Money(100 - 3 * 15));
+        assert_eq!(rem, Sells(Quantity(1), Quantity(4), Quantity(0)));
+        assert_eq!(market.update_price(rem, food), Money(15));
+    }
+
+    #[test]
+    fn better_price_first() {
+        let (mut agents, mut market, food) = setup();
+        market.limit_order(0, food, Quantity(1), Money(10));
+        market.limit_order(1, food, Quantity(1), Money(12));
+        market.limit_order(2, food, Quantity(-1), Money(9));
+
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+
+        assert_eq!(agents[&1].res[&food], Quantity(11));
+        assert_eq!(agents[&1].cash, Money(88));
+        assert_eq!(agents[&0].res[&food], Quantity(10));
+        assert_eq!(rem, Buys(Quantity(1), Quantity(2), Quantity(0)));
+    }
+
+    #[test]
+    fn no_cross_no_trade() {
+        let (mut agents, mut market, food) = setup();
+        market.limit_order(0, food, Quantity(1), Money(10));
+        market.limit_order(1, food, Quantity(-1), Money(14));
+
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+
+        assert_eq!(rem, All(Quantity(0), Quantity(0)));
+        assert_eq!(agents[&0].cash, Money(100));
+        // no trades, price moves to the middle of the spread
+        assert_eq!(market.update_price(rem, food), Money(12));
+    }
+
+    #[test]
+    fn buy_from_book() {
+        let (mut agents, mut market, food) = setup();
+        let mut ledger = Ledger::default();
+        market.limit_order(0, food, Quantity(-2), Money(15));
+        market.limit_order(1, food, Quantity(-1), Money(12));
+        market.execute_trade(&mut agents, food, &mut ledger);
+
+        // best ask first, each at its own price
+        let fill = market.buy(&mut agents, 2, food, Quantity(2), &mut ledger).unwrap();
+        assert_eq!(fill, Fill { qty: Quantity(2), value: Money(27) });
+        assert_eq!(agents[&1].res[&food], Quantity(9));
+        assert_eq!(agents[&0].res[&food], Quantity(9));
+
+        // one unit left standing
+        let fill = market.buy(&mut agents, 2, food, Quantity(5), &mut ledger).unwrap();
+        assert_eq!(fill.qty, Quantity(1));
+        assert_eq!(agents[&2].res[&food], Quantity(13));
+    }
+
+    #[test]
+    fn buyer_out_of_cash() {
+        let (mut agents, mut market, food) = setup();
+        agents.get_mut(&0).unwrap().cash = Money(25);
+        market.limit_order(1, food, Quantity(-3), Money(10));
+        market.limit_order(0, food, Quantity(3), Money(10));
+
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+
+        assert_eq!(agents[&0].res[&food], Quantity(12));
+        assert_eq!(agents[&0].cash, Money(5));
+        // the bid's last unit is dropped, the ask it would have taken stays unfilled
+        assert_eq!(rem, Sells(Quantity(1), Quantity(3), Quantity(1)));
+    }
+
+    #[test]
+    fn market_orders_dont_rest() {
+        let (mut agents, mut market, food) = setup();
+        market.limit_order(0, food, Quantity(1), Money(12));
+        market.trade((agents[&1].cash, 1), food, Quantity(-3)).unwrap();
+        market.limit_order(2, food, Quantity(1), Money(5));
+
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+
+        // one unit goes to the bid there was, the rest isn't given away to the later bid
+        assert_eq!((agents[&1].res[&food], agents[&1].cash), (Quantity(9), Money(112)));
+        assert_eq!(agents[&2].res[&food], Quantity(10));
+        assert_eq!(rem, Buys(Quantity(1), Quantity(2), Quantity(0)));
+        assert_eq!(market.update_price(rem, food), Money(12));
+
+        // a buy with nothing to take leaves no bid behind to set the price
+        market.trade((agents[&0].cash, 0), food, Quantity(2)).unwrap();
+        let rem = market.execute_trade(&mut agents, food, &mut Ledger::default());
+        assert_eq!(market.update_price(rem, food), Money(12));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+
+use failure::Error;
+
+use crate::market::UnexecutedTrades;
+use crate::market::UnexecutedTrades::{All, Buys, Sells};
+use crate::units::Money;
+
+/// Rule a `ClearingMarket` uses to move the price of one good between trade rounds.
+/// One adjuster per good, so it may keep state across rounds.
+pub trait PriceAdjuster {
+    /// New price from the current price `p0`, the one before it `p1`,
+    /// the unexecuted trades of the previous round `old` and of this round `ts`
+    fn adjust(&mut self, p0: Money, p1: Money, old: UnexecutedTrades, ts: UnexecutedTrades) -> Money;
+}
+
+/// Price adjustment rule, as written in a scenario
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceRule {
+    #[default]
+    Adaptive,
+    Proportional { gain: f64 },
+    Pid { kp: f64, ki: f64, kd: f64 },
+    Smoothed { gain: f64, window: usize, decay: f64 },
+    BoundedStep { gain: f64, max_step: f64 },
+}
+
+impl PriceRule {
+    /// Errors on a parameter out of its range: negative gains, an empty window, a decay
+    /// outside 0..1 or a step that isn't a fraction of the price
+    pub fn check(&self, ctx: &str) -> Result<(), Error> {
+        let ok = match *self {
+            PriceRule::Adaptive => true,
+            PriceRule::Proportional { gain } => gain >= 0.,
+            PriceRule::Pid { kp, ki, kd } => kp >= 0. && ki >= 0. && kd >= 0.,
+            PriceRule::Smoothed { gain, window, decay } => gain >= 0. && window >= 1 && (0. ..=1.).contains(&decay),
+            PriceRule::BoundedStep { gain, max_step } => gain >= 0. && max_step > 0. && max_step <= 1.,
+        };
+        if !ok {
+            bail!("{} price rule {:?} has a parameter out of range", ctx, self);
+        }
+        Ok(())
+    }
+
+    /// Panics on a rule that fails `check`
+    pub fn build(&self) -> Box<dyn PriceAdjuster> {
+        match *self {
+            PriceRule::Adaptive => Box::new(Adaptive),
+            PriceRule::Proportional { gain } => Box::new(Proportional { gain }),
+            PriceRule::Pid { kp, ki, kd } => Box::new(Pid::new(kp, ki, kd)),
+            PriceRule::Smoothed { gain, window, decay } => Box::new(Smoothed::new(gain, window, decay)),
+            PriceRule::BoundedStep { gain, max_step } => Box::new(BoundedStep { gain, max_step }),
+        }
+    }
+}
+
+/// Share of the round's volume left unexecuted, positive if buyers were left over
+/// and negative if sellers were
+pub fn excess_ratio(ts: UnexecutedTrades) -> f64 {
+    match ts {
+        Buys(u, v, _) if v.0 > 0 => u.0 as f64 / v.0 as f64,
+        Sells(u, v, _) if v.0 > 0 => -u.0 as f64 / v.0 as f64,
+        _ => 0.,
+    }
+}
+
+fn unex_ratio(a: UnexecutedTrades) -> f64 {
+    match a {
+        Buys(u0, e0, _) => u0.0 as f64 / e0.0 as f64,
+        Sells(u0, e0, _) => -u0.0 as f64 / e0.0 as f64,
+        All(..) => 1.
+    }
+}
+
+fn to_price(p: f64) -> Money {
+    Money(p.round().max(0.) as i64)
+}
+
+/// `p0` scaled by `1 + f`, moved at least 1 the way `f` points and never below 1, so that
+/// a rule scaling the price can't get stuck at a small one
+fn scale_price(p0: Money, f: f64) -> Money {
+    let p = if f == 0. { p0 } else { p0.nudge(f) };
+    p.max(Money(1))
+}
+
+/// The original rule: a quarter of the excess ratio after a cleared round, otherwise
+/// scale the last change by how the excess moved, damping when it flipped side
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptive;
+
+impl PriceAdjuster for Adaptive {
+    fn adjust(&mut self, p0: Money, p1: Money, old_unex: UnexecutedTrades, ts: UnexecutedTrades) -> Money {
+        let (p0, p1) = (p0.0 as f64, p1.0 as f64);
+        let dp = p0 - p1; // dp > 0 if price increased
+        to_price(match ts {
+            All(..) => p0,
+            Buys(..) | Sells(..) => {
+                match old_unex {
+                    All(..) => p0 * (1. + 0.25 * unex_ratio(ts)),
+                    old => {
+                        let r0 = unex_ratio(ts);
+                        let r1 = unex_ratio(old);
+                        if r0 * r1 < 0. { // if the sell -> buy or buy -> sell...
+                            p0 + dp * 0.5 * r0 / r1
+                        } else if dp.abs() > 0.5 {
+                            p0 + dp * 1.5 * r0 / r1
+                        } else {
+                            p0 + 2. * r0 / r1.abs()
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Move the price by `gain` times the excess ratio
+#[derive(Debug, Clone, Copy)]
+pub struct Proportional {
+    pub gain: f64,
+}
+
+impl PriceAdjuster for Proportional {
+    fn adjust(&mut self, p0: Money, _p1: Money, _old: UnexecutedTrades, ts: UnexecutedTrades) -> Money {
+        scale_price(p0, self.gain * excess_ratio(ts))
+    }
+}
+
+/// PID controller driving the excess ratio to zero
+#[derive(Debug, Clone)]
+pub struct Pid {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    integral: f64,
+    last_error: f64,
+}
+
+impl Pid {
+    pub fn new(kp: f64, ki: f64, kd: f64) -> Pid {
+        Pid { kp, ki, kd, integral: 0., last_error: 0. }
+    }
+}
+
+impl PriceAdjuster for Pid {
+    fn adjust(&mut self, p0: Money, _p1: Money, _old: UnexecutedTrades, ts: UnexecutedTrades) -> Money {
+        let e = excess_ratio(ts);
+        self.integral += e;
+        let derivative = e - self.last_error;
+        self.last_error = e;
+        let u = self.kp * e + self.ki * self.integral + self.kd * derivative;
+        scale_price(p0, u)
+    }
+}
+
+/// Proportional to an exponentially weighted average of the excess ratio over the
+/// last `window` rounds, each round back weighted `decay` times the one after it
+#[derive(Debug, Clone)]
+pub struct Smoothed {
+    pub gain: f64,
+    pub window: usize,
+    pub decay: f64,
+    history: VecDeque<f64>,
+}
+
+impl Smoothed {
+    /// `window` must be at least 1
+    pub fn new(gain: f64, window: usize, decay: f64) -> Smoothed {
+        assert!(window >= 1, "smoothing window must be at least 1");
+        Smoothed { gain, window, decay, history: VecDeque::with_capacity(window) }
+    }
+}
+
+impl PriceAdjuster for Smoothed {
+    fn adjust(&mut self, p0: Money, _p1: Money, _old: UnexecutedTrades, ts: UnexecutedTrades) -> Money {
+        if self.history.len() == self.window {
+            self.history.pop_back();
+        }
+        self.history.push_front(excess_ratio(ts));
+        let (mut sum, mut weights, mut w) = (0., 0., 1.);
+        for e in &self.history {
+            sum += w * e;
+            weights += w;
+            w *= self.decay;
+        }
+        scale_price(p0, self.gain * sum / weights)
+    }
+}
+
+/// Proportional, but never moves more than `max_step` of the price in one round
+/// and always at least one unit while the market doesn't clear
+#[derive(Debug, Clone, Copy)]
+pub struct BoundedStep {
+    pub gain: f64,
+    pub max_step: f64,
+}
+
+impl PriceAdjuster for BoundedStep {
+    fn adjust(&mut self, p0: Money, _p1: Money, _old: UnexecutedTrades, ts: UnexecutedTrades) -> Money {
+        let e = excess_ratio(ts);
+        let p = p0.0 as f64;
+        let step = (p * self.gain * e).max(-p * self.max_step).min(p * self.max_step);
+        let step = if e != 0. && step.abs() < 1. { e.signum() } else { step };
+        to_price(p + step).max(Money(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::units::Quantity;
+
+    use super::*;
+
+    fn q(n: i32) -> Quantity {
+        Quantity(n)
+    }
+
+    #[test]
+    fn proportional() {
+        let mut adj = PriceRule::Proportional { gain: 0.5 }.build();
+        assert_eq!(adj.adjust(Money(20), Money(20), All(q(0), q(0)), Buys(q(5), q(10), q(0))), Money(25));
+        assert_eq!(adj.adjust(Money(20), Money(20), All(q(0), q(0)), Sells(q(5), q(10), q(0))), Money(15));
+        assert_eq!(adj.adjust(Money(20), Money(20), All(q(0), q(0)), All(q(10), q(0))), Money(20));
+        // never falls to 0, and rises from 1 even if scaling rounds back to it
+        assert_eq!(adj.adjust(Money(1), Money(1), All(q(0), q(0)), Sells(q(10), q(10), q(0))), Money(1));
+        assert_eq!(adj.adjust(Money(1), Money(1), All(q(0), q(0)), Buys(q(1), q(100), q(0))), Money(2));
+    }
+
+    #[test]
+    fn rejects_bad_parameters() {
+        assert!(PriceRule::Pid { kp: 0.2, ki: 0.05, kd: 0.1 }.check("Food").is_ok());
+        assert!(PriceRule::Proportional { gain: -0.5 }.check("Food").is_err());
+        assert!(PriceRule::Smoothed { gain: 0.25, window: 0, decay: 0.7 }.check("Food").is_err());
+        assert!(PriceRule::Smoothed { gain: 0.25, window: 5, decay: 1.5 }.check("Food").is_err());
+        assert!(PriceRule::BoundedStep { gain: 0.25, max_step: 0. }.check("Food").is_err());
+    }
+
+    #[test]
+    fn pid_integrates() {
+        let mut adj = Pid::new(0., 0.1, 0.);
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Buys(q(5), q(10), q(0))), Money(105));
+        // same excess again, the integral term doubles
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Buys(q(5), q(10), q(0))), Money(110));
+        // cleared round, integral still pushes up
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), All(q(10), q(0))), Money(110));
+    }
+
+    #[test]
+    fn smoothed_window() {
+        let mut adj = Smoothed::new(1., 2, 1.);
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Buys(q(5), q(10), q(0))), Money(150));
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Sells(q(5), q(10), q(0))), Money(100));
+        // the first round has left the window
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Sells(q(5), q(10), q(0))), Money(50));
+    }
+
+    #[test]
+    fn bounded_step() {
+        let mut adj = BoundedStep { gain: 1., max_step: 0.1 };
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Buys(q(9), q(10), q(0))), Money(110));
+        assert_eq!(adj.adjust(Money(100), Money(100), All(q(0), q(0)), Sells(q(1), q(100), q(0))), Money(99));
+        assert_eq!(adj.adjust(Money(5), Money(5), All(q(0), q(0)), Buys(q(1), q(100), q(0))), Money(6));
+        assert_eq!(adj.adjust(Money(1), Money(1), All(q(0), q(0)), Sells(q(9), q(10), q(0))), Money(1));
+    }
+}
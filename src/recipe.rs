@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::goods::{Good, Task};
+use crate::market::Market;
+use crate::units::{Money, Quantity};
+
+/// Tasks seen as a graph from the goods they need to the goods they make, to value goods by
+/// what they can be turned into however many tasks away
+pub struct RecipeGraph<'a> {
+    tasks: &'a [Task],
+}
+
+impl<'a> RecipeGraph<'a> {
+    pub fn new(tasks: &'a [Task]) -> RecipeGraph<'a> {
+        RecipeGraph { tasks }
+    }
+
+    /// Tasks that use up `good` or need it as a tool
+    pub fn uses(&self, good: Good) -> impl Iterator<Item=&'a Task> {
+        self.tasks.iter().filter(move |t| t.needs().any(|(g, _)| g == good))
+    }
+
+    /// Tasks that make `good`, as their output or a by-product
+    pub fn producers(&self, good: Good) -> impl Iterator<Item=&'a Task> {
+        self.tasks.iter().filter(move |t| t.outputs(1.0).any(|(g, _)| g == good))
+    }
+
+    /// Goods that go into making `good`, directly or through other tasks
+    pub fn upstream(&self, good: Good) -> BTreeSet<Good> {
+        self.reachable(good, |g| self.producers(g).flat_map(|t| t.needs()).map(|(g, _)| g).collect())
+    }
+
+    /// Goods that `good` goes into, directly or through other tasks
+    pub fn downstream(&self, good: Good) -> BTreeSet<Good> {
+        self.reachable(good, |g| self.uses(g).flat_map(|t| t.outputs(1.0)).map(|(g, _)| g).collect())
+    }
+
+    fn reachable(&self, good: Good, next: impl Fn(Good) -> Vec<Good>) -> BTreeSet<Good> {
+        let mut seen = BTreeSet::new();
+        let mut todo = next(good);
+        while let Some(g) = todo.pop() {
+            if seen.insert(g) {
+                todo.extend(next(g));
+            }
+        }
+        seen
+    }
+
+    /// What each good in a task is worth: its market price, or what it makes in its best use
+    /// if that is more. Every task is revisited once per good, so chains as long as there are
+    /// goods are followed all the way and cycles can't grow values forever.
+    pub fn values(&self, market: &dyn Market) -> BTreeMap<Good, Money> {
+        let mut values: BTreeMap<Good, Money> = self.tasks.iter()
+            .flat_map(|t| t.needs().chain(t.outputs(1.0)))
+            .map(|(g, _)| (g, market.price(g)))
+            .collect();
+        for _ in 0..values.len() {
+            let better: Vec<_> = values.iter()
+                .filter_map(|(&g, &v)| match self.use_value(g, &values, market) {
+                    Some((u, _)) if u > v => Some((g, u)),
+                    _ => None,
+                })
+                .collect();
+            if better.is_empty() {
+                break;
+            }
+            values.extend(better);
+        }
+        values
+    }
+
+    /// Worth of a unit of `good` in the task that makes most of it, with the units that task
+    /// needs. Outputs are worth their derived `values`, everything else is bought at market
+    /// prices. None if no task needs it.
+    pub fn best_use(&self, good: Good, market: &dyn Market) -> Option<(Money, Quantity)> {
+        self.use_value(good, &self.values(market), market)
+    }
+
+    fn use_value(&self, good: Good, values: &BTreeMap<Good, Money>, market: &dyn Market) -> Option<(Money, Quantity)> {
+        self.uses(good)
+            .map(|task| {
+                // saturate, a huge value only makes the good more attractive
+                let revenue = task.outputs(1.0)
+                    .fold(Money::ZERO, |total, (g, amt)| {
+                        let worth = values.get(&g).cloned().unwrap_or_else(|| market.price(g));
+                        total.saturating_add(worth.saturating_mul(amt))
+                    });
+                let inputs = task.inputs.iter()
+                    .filter(|&&(g, _)| g != good)
+                    .fold(Money::ZERO, |total, &(g, amt)| total.saturating_add(market.price(g).saturating_mul(amt)));
+                let wear = task.tools.iter()
+                    .filter(|t| t.good != good)
+                    .fold(Money::ZERO, |total, t| {
+                        total.saturating_add(market.price(t.good).checked_div(Quantity(t.life as i32)).unwrap_or(Money::ZERO))
+                    });
+                let net = revenue.saturating_sub(inputs).saturating_sub(wear);
+                match task.inputs.iter().find(|&&(g, _)| g == good) {
+                    Some(&(_, amt)) => (net.checked_div(amt).unwrap_or(Money::ZERO), amt),
+                    // a tool makes a task's net value every use until a unit wears out
+                    None => {
+                        let tool = task.tools.iter().find(|t| t.good == good).unwrap();
+                        let lifetime = net.saturating_mul(Quantity(tool.life as i32));
+                        (lifetime.checked_div(tool.qty).unwrap_or(Money::ZERO), tool.qty)
+                    }
+                }
+            })
+            .max_by_key(|&(v, _)| v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::goods::Tool;
+    use crate::testing::{goods, market};
+
+    use super::*;
+
+    #[test]
+    fn values_through_chains() {
+        let (_, [food, flour, grain, straw, stone]) = goods(["Food", "Flour", "Grain", "Straw", "Millstone"]);
+        let tasks = [
+            Task::new("Farm", &[], (grain, Quantity(10))).with_byproducts(&[(straw, Quantity(5))]),
+            Task::new("Mill", &[(grain, Quantity(20))], (flour, Quantity(10)))
+                .with_tools(&[Tool { good: stone, qty: Quantity(1), life: 10 }]),
+            Task::new("Bake", &[(flour, Quantity(10)), (straw, Quantity(2))], (food, Quantity(10))),
+        ];
+        let market = market(
+            hashmap! {food => Money(30), flour => Money(5), grain => Money(1), straw => Money(1), stone => Money(50)});
+        let graph = RecipeGraph::new(&tasks);
+
+        assert_eq!(graph.upstream(food), [flour, grain, straw, stone].iter().cloned().collect());
+        assert_eq!(graph.downstream(straw), [food].iter().cloned().collect());
+        assert_eq!(graph.producers(straw).map(|t| &t.name[..]).collect::<Vec<_>>(), ["Farm"]);
+
+        let values = graph.values(&market);
+        // 10 food for 300 less 2 straw, over 10 flour
+        assert_eq!(values[&flour], Money(29));
+        // 10 flour worth 290 less 5 of wear on the stone, over 20 grain
+        assert_eq!(values[&grain], Money(14));
+        // nothing uses food, it's worth its price
+        assert_eq!(values[&food], Money(30));
+        assert_eq!(graph.best_use(food, &market), None);
+        // 280 less 20 of grain every use, for 10 uses
+        assert_eq!(graph.best_use(stone, &market), Some((Money(2700), Quantity(1))));
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs::{create_dir_all, File};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard};
 
 use csv::Writer;
@@ -17,19 +17,27 @@ lazy_static! {
     static ref REC: Mutex<Recorder> = Mutex::new(Recorder::new());
 }
 
-pub fn init_recorder(run_name: impl Into<String>, over_write: bool) {
+/// Start recording into `out_dir/run_name/`, returns the run directory.
+/// Without `over_write` a random suffix is added so earlier runs are kept.
+pub fn init_recorder(out_dir: impl AsRef<Path>, run_name: impl Into<String>, over_write: bool) -> PathBuf {
     let run_name = run_name.into();
-    let path = if over_write {
-        "./data/".to_string() + &run_name + "/"
+    let dir_name = if over_write {
+        run_name
     } else {
         let rand = rand::rngs::SmallRng::from_entropy()
             .sample_iter(&Alphanumeric)
             .take(3)
             .collect::<String>();
-        "./data/".to_string() + &run_name + "_" + &rand + "/"
+        run_name + "_" + &rand
     };
+    let path = out_dir.as_ref().join(dir_name);
     create_dir_all(&path).unwrap();
-    REC.lock().unwrap().directory = path;
+    let mut rec = REC.lock().unwrap();
+    rec.flush();
+    rec.files.clear();
+    rec.tick = 0;
+    rec.directory = path.to_string_lossy().into_owned() + "/";
+    path
 }
 
 pub fn register(name: impl AsRef<str>, col_names: &[&str]) {
@@ -78,9 +86,14 @@ impl Recorder {
         Ok(())
     }
 
+    // records that were never registered are dropped, so library users can run without a recorder
     pub fn add(&mut self, name: &str, blob: impl Serialize + Debug) -> Result<(), impl Fail> {
-        let w = self.files.get_mut(name).unwrap();
-        w.write_field(self.tick.to_string())?;
-        w.serialize(blob)
+        match self.files.get_mut(name) {
+            Some(w) => {
+                w.write_field(self.tick.to_string())?;
+                w.serialize(blob)
+            }
+            None => Ok(()),
+        }
     }
 }
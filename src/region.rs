@@ -0,0 +1,134 @@
+use failure::Error;
+use tracing::debug;
+
+use crate::agent::{AgentId, Agents};
+use crate::goods::Good;
+use crate::ledger::{Entry, Ledger};
+use crate::market::Market;
+use crate::units::{Money, Quantity};
+
+/// Index of a region, in the order the scenario lists them
+pub type RegionId = u8;
+
+/// A town with its own market. Agents only trade in the market of their home region.
+pub struct Region {
+    pub name: String,
+    pub market: Box<dyn Market>,
+}
+
+/// One way connection between two regions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Route {
+    pub from: RegionId,
+    pub to: RegionId,
+    /// paid per unit shipped, the cash leaves the economy
+    pub cost: Money,
+    /// ticks on the road, shipments arrive at the start of the tick `delay` ticks later
+    pub delay: u16,
+}
+
+/// Goods travelling to region `to` with their owner
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shipment {
+    pub owner: AgentId,
+    pub good: Good,
+    pub qty: Quantity,
+    pub to: RegionId,
+    pub arrives: u16,
+}
+
+/// Routes between regions and the shipments on them
+#[derive(Clone, Debug, Default)]
+pub struct Transport {
+    routes: Vec<Route>,
+    in_transit: Vec<Shipment>,
+}
+
+impl Transport {
+    pub fn new(routes: Vec<Route>) -> Transport {
+        Transport { routes, in_transit: Vec::new() }
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    pub fn in_transit(&self) -> &[Shipment] {
+        &self.in_transit
+    }
+
+    /// Whether agent `id` is on the road with a shipment
+    pub fn travelling(&self, id: AgentId) -> bool {
+        self.in_transit.iter().any(|s| s.owner == id)
+    }
+
+    /// Send `qty` of `good` held by agent `id` along `route` on `tick`, the agent travels with it.
+    /// Transport is paid up front. Nothing changes if the agent lacks the goods or the cash.
+    #[allow(clippy::too_many_arguments)]
+    pub fn ship(&mut self, agents: &mut Agents, id: AgentId, good: Good, qty: Quantity, route: Route,
+                tick: u16, ledger: &mut Ledger) -> Result<(), Error> {
+        let a = &agents[&id];
+        let cost = route.cost.checked_mul(qty)?;
+        let cash = a.cash.checked_sub(cost)?;
+        let stock = a.res[&good].checked_sub(qty)?;
+        if cash < Money::ZERO || stock < Quantity::ZERO {
+            bail!("agent {} can't ship {} {} for {}", id, qty, good, cost);
+        }
+        ledger.push(Entry::Ship { agent: id, good, qty, cost, to: route.to });
+        let a = agents.get_mut(&id).unwrap();
+        a.cash = cash;
+        *a.res.get_mut(&good).unwrap() = stock;
+        self.in_transit.push(Shipment { owner: id, good, qty, to: route.to, arrives: tick + route.delay.max(1) });
+        debug!(agent = id, %good, %qty, %cost, to = route.to, "shipped");
+        Ok(())
+    }
+
+    /// Hand shipments due by `tick` to their owners, who now live where they arrived.
+    /// The goods of owners who died on the way are lost.
+    pub fn deliver(&mut self, agents: &mut Agents, tick: u16, ledger: &mut Ledger) {
+        let (arrived, travelling) = std::mem::take(&mut self.in_transit)
+            .into_iter()
+            .partition(|s| s.arrives <= tick);
+        self.in_transit = travelling;
+        for s in arrived {
+            if let Some(a) = agents.get_mut(&s.owner) {
+                a.home = s.to;
+                *a.res.get_mut(&s.good).unwrap() += s.qty;
+                ledger.push(Entry::Arrive { agent: s.owner, good: s.good, qty: s.qty });
+                debug!(agent = s.owner, good = %s.good, qty = %s.qty, to = s.to, "arrived");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::agent::Agent;
+    use crate::testing::goods;
+
+    use super::*;
+
+    #[test]
+    fn ship_and_deliver() {
+        let (_, [food]) = goods(["Food"]);
+        let mut agents = Agents::new();
+        let id = Agent::new_into_map(&mut agents, Money(50), hashmap! {food => Quantity(10)}, hashmap! {food => 1.0}).unwrap();
+        let route = Route { from: 0, to: 1, cost: Money(2), delay: 3 };
+        let mut transport = Transport::new(vec![route]);
+        let mut ledger = Ledger::default();
+
+        // can't pay for 30 units
+        assert!(transport.ship(&mut agents, id, food, Quantity(30), route, 0, &mut ledger).is_err());
+        transport.ship(&mut agents, id, food, Quantity(8), route, 0, &mut ledger).unwrap();
+        assert_eq!((agents[&id].cash, agents[&id].res[&food]), (Money(34), Quantity(2)));
+        assert!(transport.travelling(id));
+
+        transport.deliver(&mut agents, 2, &mut ledger);
+        assert_eq!(agents[&id].home, 0);
+        transport.deliver(&mut agents, 3, &mut ledger);
+        assert_eq!((agents[&id].home, agents[&id].res[&food]), (1, Quantity(10)));
+        assert!(!transport.travelling(id));
+    }
+}
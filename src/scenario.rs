@@ -0,0 +1,1128 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_to_string;
+use std::iter::once;
+use std::path::Path;
+
+use failure::Error;
+use rand::distributions::uniform::SampleUniform;
+use rand::prelude::{Rng, SliceRandom, SmallRng};
+use rand::SeedableRng;
+
+use crate::agent::{Agent, AgentId, Agents, Health};
+use crate::brain::{Household, Specialist, Strategy};
+use crate::learner::{Expectation, Learner, TaskChoice};
+use crate::goods::{Good, GoodDef, Goods, Task, Tool, DEFAULT_HOURS};
+use crate::demography::{Births, Demography, Estates, Immigration};
+use crate::firm::Firm;
+use crate::labor::LaborMarket;
+use crate::land::{Land, Resource};
+use crate::market::{ClearingMarket, Market};
+use crate::merchant::Merchant;
+use crate::order_book::OrderBookMarket;
+use crate::price_adjust::PriceRule;
+use crate::region::{Region, RegionId, Route, Transport};
+use crate::tatonnement::TatonnementMarket;
+use crate::simulation::Simulation;
+use crate::trader::Trader;
+use crate::units::{Money, Quantity};
+use crate::utility::{Preferences, Utility};
+
+/// Complete description of a simulation setup, loadable from TOML, RON or JSON.
+/// See `scenarios/bread.toml` for an example.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scenario {
+    pub goods: Vec<GoodDef>,
+    pub tasks: Vec<TaskDef>,
+    pub population: Vec<Population>,
+    #[serde(default)]
+    pub merchants: Vec<MerchantDef>,
+    #[serde(default)]
+    pub traders: Vec<TraderDef>,
+    /// Firms hiring agents to work, need `labor`
+    #[serde(default)]
+    pub firms: Vec<FirmDef>,
+    pub market: MarketDef,
+    /// Towns with a market each, all built from `market`. A single region if empty.
+    #[serde(default)]
+    pub regions: Vec<RegionDef>,
+    #[serde(default)]
+    pub routes: Vec<RouteDef>,
+    /// Wage labor between agents, in every region. Agents only work for themselves if unset.
+    #[serde(default)]
+    pub labor: Option<LaborDef>,
+    /// Births, estates and immigration. Agents only ever die if unset.
+    #[serde(default)]
+    pub demography: Option<DemographyDef>,
+    /// Fields and deposits tasks draw on. Tasks make goods from their inputs alone if empty.
+    #[serde(default)]
+    pub resources: Vec<ResourceDef>,
+    pub consumption: ConsumptionDef,
+    pub ticks: u16,
+    /// Master seed for all randomness in the run, a random seed is picked if unset
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Panic as soon as a tick changes total cash or goods in a way the ledger doesn't record
+    #[serde(default)]
+    pub check_invariants: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskDef {
+    pub name: String,
+    #[serde(default)]
+    pub inputs: HashMap<Good, Quantity>,
+    pub output: HashMap<Good, Quantity>,
+    /// made alongside the output, in the same proportion to skill
+    #[serde(default)]
+    pub byproducts: HashMap<Good, Quantity>,
+    #[serde(default)]
+    pub tools: HashMap<Good, ToolDef>,
+    /// hours it takes, agents work `hours` a tick
+    #[serde(default = "default_hours")]
+    pub labor: u16,
+    /// units of a resource taken each time it's performed, at most one resource
+    #[serde(default)]
+    pub draws: HashMap<String, Quantity>,
+}
+
+/// Starting wage an hour and the fraction it moves each tick, see `LaborMarket`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LaborDef {
+    pub wage: Money,
+    #[serde(default = "default_wage_gain")]
+    pub gain: f64,
+}
+
+/// See `Demography`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DemographyDef {
+    #[serde(default)]
+    pub births: Option<Births>,
+    #[serde(default)]
+    pub estates: Estates,
+    #[serde(default)]
+    pub immigration: Option<ImmigrationDef>,
+}
+
+/// Each tick, with chance `rate`, `population.count` agents drawn from `population` arrive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImmigrationDef {
+    pub rate: f64,
+    pub population: Population,
+}
+
+/// `qty` units must be held to perform the task, one wears out every `life` uses
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolDef {
+    #[serde(default = "default_tool_qty")]
+    pub qty: Quantity,
+    pub life: u16,
+}
+
+/// A group of agents whose starting state is drawn from the given distributions.
+/// Goods missing from `resources` start at 0, goods missing from `skills` at 1.0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Population {
+    pub count: usize,
+    /// home region, the first if unset
+    #[serde(default)]
+    pub region: Option<String>,
+    pub cash: Dist<i64>,
+    #[serde(default)]
+    pub resources: HashMap<Good, Dist<i32>>,
+    #[serde(default)]
+    pub skills: HashMap<Good, Dist<f32>>,
+    #[serde(default)]
+    pub strategy: StrategyDef,
+    /// hours of work each tick, split between tasks and work for wages
+    #[serde(default = "default_hours")]
+    pub hours: u16,
+    /// what its agents enjoy eating, the scenario's `consumption` if unset
+    #[serde(default)]
+    pub utility: Option<Utility>,
+}
+
+/// Decision rules of a population's agents
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyDef {
+    /// trade, produce and eat by marginal utility
+    #[default]
+    Household,
+    /// always perform the named task, otherwise a household
+    Specialist { task: String },
+    /// form price expectations from history, see `Learner`
+    Learner {
+        expectation: Expectation,
+        #[serde(default)]
+        tasks: TaskChoice,
+        #[serde(default)]
+        patience: f64,
+    },
+}
+
+impl StrategyDef {
+    /// Only learners draw from `rng`, to seed their own
+    pub fn build(&self, rng: &mut impl Rng) -> Strategy {
+        match self {
+            StrategyDef::Household => Strategy::Household(Household),
+            StrategyDef::Specialist { task } => Strategy::Specialist(Specialist { task: task.clone() }),
+            StrategyDef::Learner { expectation, tasks, patience } =>
+                Strategy::Learner(Learner::new(*expectation, *tasks, *patience, SmallRng::seed_from_u64(rng.gen()))),
+        }
+    }
+}
+
+/// Merchants dealing in the goods in `stock`, which is also the inventory they aim to hold.
+/// Their starting price estimates are the market's starting prices.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MerchantDef {
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// home region, the first if unset
+    #[serde(default)]
+    pub region: Option<String>,
+    pub cash: i64,
+    pub stock: HashMap<Good, Quantity>,
+    /// half the bid-ask spread at target inventory, as a fraction of the price
+    pub spread: f64,
+    /// how strongly inventory away from target shifts and widens the quotes
+    #[serde(default = "default_merchant_risk")]
+    pub risk: f64,
+    /// most units bought or sold per good and round
+    pub depth: Quantity,
+    /// weight of the latest price in the fair price estimate
+    #[serde(default = "default_merchant_smoothing")]
+    pub smoothing: f64,
+}
+
+/// Firms owned by agents of their region, see `Firm`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FirmDef {
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// home region, the first if unset
+    #[serde(default)]
+    pub region: Option<String>,
+    pub cash: i64,
+    /// starting inventory and capital goods
+    #[serde(default)]
+    pub stock: HashMap<Good, Quantity>,
+    /// names of the tasks it hires for
+    pub tasks: Vec<String>,
+    /// runs of each task it keeps the inputs and tools for
+    #[serde(default = "default_batches")]
+    pub batches: u16,
+    /// agents drawn from the region's population to own equal shares
+    #[serde(default = "default_count")]
+    pub owners: usize,
+    /// cash kept for wages and inputs, the rest is paid to the owners every tick
+    #[serde(default)]
+    pub reserve: Money,
+}
+
+/// Fields or deposits of one kind in a region, see `Resource`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResourceDef {
+    pub name: String,
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// region it's in, the first if unset
+    #[serde(default)]
+    pub region: Option<String>,
+    pub capacity: Quantity,
+    /// starting stock, full if unset
+    #[serde(default)]
+    pub stock: Option<Quantity>,
+    /// units it regrows each tick, none for a deposit that runs out
+    #[serde(default)]
+    pub regrowth: Quantity,
+    /// most units drawn from each a tick
+    pub limit: Quantity,
+    /// each owned by an agent of its region drawn at random, common land if not
+    #[serde(default)]
+    pub owned: bool,
+    /// starting rent a unit, paid by everyone but the owner
+    #[serde(default)]
+    pub rent: Money,
+    /// fraction the rent moves each tick
+    #[serde(default = "default_rent_gain")]
+    pub gain: f64,
+}
+
+/// Traders arbitraging between regions, see `Trader`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TraderDef {
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// region they start out in, the first if unset
+    #[serde(default)]
+    pub region: Option<String>,
+    pub cash: i64,
+    /// most units carried per trip
+    pub capacity: Quantity,
+    /// least profit per unit, after transport, worth a trip
+    #[serde(default)]
+    pub min_margin: Money,
+    /// fraction the asking price of unsold cargo drops each tick
+    #[serde(default = "default_markdown")]
+    pub markdown: f64,
+}
+
+/// A town, starting at the market's prices except where `prices` says otherwise
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegionDef {
+    pub name: String,
+    #[serde(default)]
+    pub prices: HashMap<Good, Money>,
+}
+
+/// Connection between two regions, usable both ways unless `one_way`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteDef {
+    pub from: String,
+    pub to: String,
+    /// paid per unit shipped
+    pub cost: Money,
+    /// ticks on the road
+    pub delay: u16,
+    #[serde(default)]
+    pub one_way: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarketDef {
+    #[serde(default)]
+    pub kind: MarketKind,
+    pub prices: HashMap<Good, Money>,
+    #[serde(default = "default_trade_rounds")]
+    pub trade_rounds: u8,
+    #[serde(default)]
+    pub tatonnement: TatonnementDef,
+    /// Price adjustment of the clearing market for goods not in `price_rules`
+    #[serde(default)]
+    pub default_price_rule: PriceRule,
+    #[serde(default)]
+    pub price_rules: HashMap<Good, PriceRule>,
+}
+
+/// Search limits of the tatonnement market
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TatonnementDef {
+    pub max_iters: u32,
+    pub tolerance: Money,
+}
+
+impl Default for TatonnementDef {
+    fn default() -> Self {
+        TatonnementDef { max_iters: 30, tolerance: Money(1) }
+    }
+}
+
+/// What agents eat: a single `good` valued by `mu`, or a `utility` over several goods
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsumptionDef {
+    #[serde(default)]
+    pub good: Option<Good>,
+    /// marginal utility of each successive unit of `good` consumed this tick
+    #[serde(default)]
+    pub mu: Vec<Money>,
+    /// utility of the basket eaten in a tick, instead of `good` and `mu`
+    #[serde(default)]
+    pub utility: Option<Utility>,
+    /// of a unit kept to eat in a later tick
+    pub discount: f64,
+    #[serde(default = "default_max_consumption")]
+    pub max: Quantity,
+    /// hunger that kills over several ticks, without it agents die on running out of food
+    #[serde(default)]
+    pub health: Option<Health>,
+}
+
+/// Trading institution the market uses
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketKind {
+    /// posted prices, adjusted between rounds by the unexecuted volume
+    #[default]
+    Clearing,
+    /// continuous double auction over limit orders
+    OrderBook,
+    /// searches for the market clearing price every round
+    Tatonnement,
+}
+
+fn default_trade_rounds() -> u8 { 2 }
+
+fn default_max_consumption() -> Quantity { Quantity(5) }
+
+fn default_count() -> usize { 1 }
+
+fn default_hours() -> u16 { DEFAULT_HOURS }
+
+fn default_wage_gain() -> f64 { 0.1 }
+
+fn default_batches() -> u16 { 2 }
+
+fn default_tool_qty() -> Quantity { Quantity(1) }
+
+fn default_merchant_risk() -> f64 { 1.0 }
+
+fn default_merchant_smoothing() -> f64 { 0.3 }
+
+fn default_markdown() -> f64 { 0.1 }
+
+fn default_rent_gain() -> f64 { 0.1 }
+
+/// Name of the only region of scenarios that don't define any
+const DEFAULT_REGION: &str = "main";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dist<T> {
+    Fixed(T),
+    /// `[low, high)`, just `low` if they're equal
+    Uniform([T; 2]),
+    Choice(Vec<T>),
+}
+
+impl<T: SampleUniform + Copy + PartialOrd> Dist<T> {
+    /// Errors if nothing can be drawn: a range ending below its start or an empty choice
+    pub fn check(&self, ctx: &str) -> Result<(), Error> {
+        match self {
+            Dist::Uniform([low, high]) if high < low => bail!("{} range must not end below its start", ctx),
+            Dist::Choice(xs) if xs.is_empty() => bail!("{} must have something to choose from", ctx),
+            _ => Ok(()),
+        }
+    }
+
+    /// Panics on a distribution that fails `check`
+    pub fn sample(&self, rng: &mut impl Rng) -> T {
+        match self {
+            Dist::Fixed(x) => *x,
+            Dist::Uniform([low, high]) if low >= high => *low,
+            Dist::Uniform([low, high]) => rng.gen_range(*low, *high),
+            Dist::Choice(xs) => *xs.choose(rng).expect("choice distribution must be non-empty"),
+        }
+    }
+}
+
+/// Just the goods of a scenario, read before the rest
+#[derive(Deserialize)]
+#[serde(rename = "Scenario")]
+struct ScenarioGoods {
+    goods: Vec<GoodDef>,
+}
+
+/// Load a scenario file and build it into a ready-to-run simulation
+pub fn load(path: impl AsRef<Path>) -> Result<Simulation, Error> {
+    Scenario::load(path)?.build()
+}
+
+impl Scenario {
+    /// Format is picked from the file extension: `.toml`, `.ron` or `.json`
+    pub fn load(path: impl AsRef<Path>) -> Result<Scenario, Error> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(format @ "toml") | Some(format @ "ron") | Some(format @ "json") =>
+                Scenario::parse(&read_to_string(path)?, format),
+            _ => bail!("unknown scenario format: {}", path.display()),
+        }
+    }
+
+    /// Parse a scenario in `format`, `toml`, `ron` or `json`. Goods are read first,
+    /// every other good name must be one of them.
+    pub fn parse(s: &str, format: &str) -> Result<Scenario, Error> {
+        match format {
+            "toml" => Goods::new(toml::from_str::<ScenarioGoods>(s)?.goods)?
+                .resolving(|| Ok(toml::from_str(s)?)),
+            "ron" => Goods::new(ron::de::from_str::<ScenarioGoods>(s)?.goods)?
+                .resolving(|| Ok(ron::de::from_str(s)?)),
+            "json" => Goods::new(serde_json::from_str::<ScenarioGoods>(s)?.goods)?
+                .resolving(|| Ok(serde_json::from_str(s)?)),
+            _ => bail!("unknown scenario format: {}", format),
+        }
+    }
+
+    pub fn build(&self) -> Result<Simulation, Error> {
+        let goods = Goods::new(self.goods.clone())?;
+        let check = |good: &Good, ctx: &str| -> Result<(), Error> {
+            if goods.contains(*good) {
+                Ok(())
+            } else {
+                bail!("{} refers to undefined good {}", ctx, good)
+            }
+        };
+
+        let mut tasks = Vec::with_capacity(self.tasks.len());
+        for t in &self.tasks {
+            t.inputs.keys().chain(t.output.keys()).chain(t.byproducts.keys()).chain(t.tools.keys())
+                .try_for_each(|g| check(g, &t.name))?;
+            tasks.push(t.to_task()?);
+        }
+
+        for g in self.market.prices.keys().chain(self.market.price_rules.keys()) {
+            check(g, "market")?;
+        }
+        self.market.default_price_rule.check("market default")?;
+        for (g, rule) in &self.market.price_rules {
+            rule.check(&g.to_string())?;
+        }
+        if let Some(g) = goods.all().iter().find(|g| !self.market.prices.contains_key(g)) {
+            bail!("market has no starting price for {}", g);
+        }
+        let tastes = |utility: Option<&Utility>, ctx: &str| -> Result<Preferences, Error> {
+            let p = self.consumption.preferences(utility)?;
+            p.utility.goods().iter().try_for_each(|g| check(g, ctx))?;
+            Ok(p.with_goods(&goods))
+        };
+        tastes(None, "consumption")?;
+        if let Some(h) = &self.consumption.health {
+            if h.need <= Quantity::ZERO || h.loss <= 0. || h.recovery < 0. {
+                bail!("health must need food, lose health when short of it and not lose it when fed");
+            }
+        }
+
+        let default_region = [RegionDef { name: DEFAULT_REGION.into(), prices: HashMap::new() }];
+        let region_defs = if self.regions.is_empty() { &default_region[..] } else { &self.regions };
+        if region_defs.len() > RegionId::MAX as usize + 1 {
+            bail!("too many regions");
+        }
+        let mut prices = Vec::with_capacity(region_defs.len());
+        for (i, r) in region_defs.iter().enumerate() {
+            if region_defs[..i].iter().any(|other| other.name == r.name) {
+                bail!("region {} is defined twice", r.name);
+            }
+            for g in r.prices.keys() {
+                check(g, &r.name)?;
+            }
+            let mut p = self.market.prices.clone();
+            p.extend(&r.prices);
+            prices.push(p);
+        }
+        let region = |name: Option<&String>, ctx: &str| -> Result<RegionId, Error> {
+            match name {
+                None => Ok(0),
+                Some(name) => match region_defs.iter().position(|r| &r.name == name) {
+                    Some(i) => Ok(i as RegionId),
+                    None => bail!("{} refers to undefined region {}", ctx, name),
+                },
+            }
+        };
+        let mut routes = Vec::new();
+        for r in &self.routes {
+            let (from, to) = (region(Some(&r.from), "route")?, region(Some(&r.to), "route")?);
+            routes.push(Route { from, to, cost: r.cost, delay: r.delay });
+            if !r.one_way {
+                routes.push(Route { from: to, to: from, cost: r.cost, delay: r.delay });
+            }
+        }
+
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut agents = Agents::new();
+        for pop in &self.population {
+            for g in pop.resources.keys().chain(pop.skills.keys()) {
+                check(g, "population")?;
+            }
+            pop.check("population")?;
+            if let StrategyDef::Specialist { task } = &pop.strategy {
+                if !self.tasks.iter().any(|t| &t.name == task) {
+                    bail!("population refers to undefined task {}", task);
+                }
+            }
+            let home = region(pop.region.as_ref(), "population")?;
+            pop.generate(&goods, home, &tastes(pop.utility.as_ref(), "population")?, &mut rng, &mut agents)?;
+        }
+        for m in &self.merchants {
+            for g in m.stock.keys() {
+                check(g, "merchant")?;
+            }
+            let home = region(m.region.as_ref(), "merchant")?;
+            m.generate(&goods, home, &prices[home as usize], &mut agents)?;
+        }
+        for t in &self.traders {
+            t.generate(&goods, region(t.region.as_ref(), "trader")?, &mut agents)?;
+        }
+        if !self.firms.is_empty() && self.labor.is_none() {
+            bail!("firms can't hire without a labor market");
+        }
+        for f in &self.firms {
+            for g in f.stock.keys() {
+                check(g, "firm")?;
+            }
+            let mut runs = Vec::with_capacity(f.tasks.len());
+            for name in &f.tasks {
+                match tasks.iter().find(|t| &t.name == name) {
+                    Some(t) => runs.push(t),
+                    None => bail!("firm refers to undefined task {}", name),
+                }
+            }
+            if let Some(t) = runs.iter().find(|t| t.draws.is_some()) {
+                bail!("firm task {} draws on land, hired hands can't work it", t.name);
+            }
+            f.generate(&goods, region(f.region.as_ref(), "firm")?, &runs, &mut rng, &mut agents)?;
+        }
+
+        for t in &tasks {
+            if let Some((name, _)) = &t.draws {
+                if !self.resources.iter().any(|r| &r.name == name) {
+                    bail!("task {} draws on undefined resource {}", t.name, name);
+                }
+            }
+        }
+        let mut resources = Vec::new();
+        for r in &self.resources {
+            if r.capacity <= Quantity::ZERO || r.limit <= Quantity::ZERO || r.regrowth < Quantity::ZERO
+                || r.rent < Money::ZERO || r.gain < 0. || r.stock.is_some_and(|s| s < Quantity::ZERO || s > r.capacity) {
+                bail!("resource {} must hold and yield something, with a stock up to its capacity and no negative regrowth or rent", r.name);
+            }
+            resources.extend(r.generate(region(r.region.as_ref(), "resource")?, &mut rng, &agents)?);
+        }
+
+        let mut regions = Vec::with_capacity(region_defs.len());
+        for (r, prices) in region_defs.iter().zip(prices) {
+            let market_rng = SmallRng::from_rng(&mut rng)?;
+            regions.push(Region { name: r.name.clone(), market: self.market.build(&goods, prices, market_rng) });
+        }
+
+        let mut demography = None;
+        if let Some(d) = &self.demography {
+            if let Some(b) = &d.births {
+                if !(0. ..=1.).contains(&b.rate) || !(0. ..=1.).contains(&b.share) || b.mutation < 0. {
+                    bail!("births must happen at a rate between 0 and 1, give the child a share between 0 and 1 and mutate skills by 0 or more");
+                }
+            }
+            let immigration = match &d.immigration {
+                Some(im) => {
+                    for g in im.population.resources.keys().chain(im.population.skills.keys()) {
+                        check(g, "immigration")?;
+                    }
+                    im.population.check("immigration")?;
+                    let home = region(im.population.region.as_ref(), "immigration")?;
+                    let preferences = tastes(im.population.utility.as_ref(), "immigration")?;
+                    Some(Immigration { rate: im.rate, population: im.population.clone(), home, preferences })
+                }
+                None => None,
+            };
+            demography = Some(Demography::new(d.births, d.estates, immigration, SmallRng::from_rng(&mut rng)?));
+        }
+
+        let mut sim = Simulation::new(goods,
+                                      tasks,
+                                      agents,
+                                      regions,
+                                      self.market.trade_rounds,
+                                      self.ticks,
+                                      seed);
+        sim.set_check_invariants(self.check_invariants);
+        sim.set_transport(Transport::new(routes));
+        if let Some(h) = self.consumption.health {
+            sim.set_health(h);
+        }
+        if let Some(d) = demography {
+            sim.set_demography(d);
+        }
+        if let Some(labor) = &self.labor {
+            sim.set_labor(vec![LaborMarket::new(labor.wage, labor.gain); region_defs.len()]);
+        }
+        sim.set_land(Land::new(resources));
+        Ok(sim)
+    }
+}
+
+impl MarketDef {
+    /// A market of this kind starting at `prices`
+    pub fn build(&self, goods: &Goods, prices: HashMap<Good, Money>, rng: SmallRng) -> Box<dyn Market> {
+        match self.kind {
+            MarketKind::Clearing => {
+                let mut market = ClearingMarket::new(prices, rng);
+                for &g in goods.all() {
+                    let rule = self.price_rules.get(&g).unwrap_or(&self.default_price_rule);
+                    market.set_adjuster(g, rule.build());
+                }
+                Box::new(market)
+            }
+            MarketKind::OrderBook => Box::new(OrderBookMarket::new(prices, rng)),
+            MarketKind::Tatonnement => {
+                let TatonnementDef { max_iters, tolerance } = self.tatonnement;
+                Box::new(TatonnementMarket::new(prices, rng, max_iters, tolerance))
+            }
+        }
+    }
+}
+
+impl TaskDef {
+    pub fn to_task(&self) -> Result<Task, Error> {
+        if self.output.len() != 1 {
+            bail!("task {} must have exactly one output", self.name);
+        }
+        if self.inputs.len() > 4 {
+            bail!("task {} has more than 4 inputs", self.name);
+        }
+        if self.byproducts.len() > 4 {
+            bail!("task {} has more than 4 by-products", self.name);
+        }
+        if self.tools.len() > 4 {
+            bail!("task {} needs more than 4 tools", self.name);
+        }
+        if self.labor == 0 {
+            bail!("task {} must take at least an hour", self.name);
+        }
+        if let Some(g) = self.tools.iter().find(|(_, t)| t.life == 0).map(|(g, _)| g) {
+            bail!("tool {} of task {} must last at least one use", g, self.name);
+        }
+        if self.draws.len() > 1 || self.draws.values().any(|&amt| amt <= Quantity::ZERO) {
+            bail!("task {} must draw some of at most one resource", self.name);
+        }
+        let mut inputs: Vec<_> = self.inputs.iter().map(|(&g, &amt)| (g, amt)).collect();
+        inputs.sort();
+        let output = self.output.iter().map(|(&g, &amt)| (g, amt)).next().unwrap();
+        let mut byproducts: Vec<_> = self.byproducts.iter().map(|(&g, &amt)| (g, amt)).collect();
+        byproducts.sort();
+        let mut tools: Vec<_> = self.tools.iter()
+            .map(|(&good, t)| Tool { good, qty: t.qty, life: t.life })
+            .collect();
+        tools.sort();
+        let task = Task::new(self.name.clone(), &inputs, output)
+            .with_byproducts(&byproducts)
+            .with_tools(&tools)
+            .with_labor(self.labor);
+        Ok(match self.draws.iter().next() {
+            Some((resource, &amt)) => task.with_draw(resource.clone(), amt),
+            None => task,
+        })
+    }
+}
+
+impl ConsumptionDef {
+    /// Preferences with `utility`, this one's own if none
+    pub fn preferences(&self, utility: Option<&Utility>) -> Result<Preferences, Error> {
+        let own = match (&self.utility, self.good) {
+            (Some(u), None) => u.clone(),
+            (None, Some(good)) => Utility::Additive(once((good, self.mu.clone())).collect()),
+            _ => bail!("consumption needs either a good or a utility"),
+        };
+        let utility = utility.cloned().unwrap_or(own);
+        let goods = utility.goods();
+        let bad = match &utility {
+            Utility::Additive(mu) => mu.values().any(|mu| mu.is_empty()),
+            Utility::CobbDouglas { weights, scale } => weights.values().any(|&w| w <= 0.) || *scale <= 0.,
+            Utility::Ces { weights, rho, scale } =>
+                weights.values().any(|&w| w <= 0.) || *rho == 0. || *rho > 1. || *scale <= 0.,
+        };
+        if goods.is_empty() || bad {
+            bail!("utility must be of at least one good, with positive weights and scale and rho up to 1 but not 0");
+        }
+        if self.max <= Quantity::ZERO {
+            bail!("consumption max must be at least 1");
+        }
+        Ok(Preferences { utility, discount: self.discount, max: self.max, perishable: BTreeMap::new() })
+    }
+}
+
+impl Population {
+    pub fn generate(&self, goods: &Goods, home: RegionId, preferences: &Preferences, rng: &mut impl Rng,
+                    agents: &mut Agents) -> Result<(), Error> {
+        for _ in 0..self.count {
+            let id = Agent::next_id(agents)?;
+            agents.insert(id, self.agent(goods, id, home, preferences, rng));
+        }
+        Ok(())
+    }
+
+    /// Errors if any of its distributions can't be drawn from
+    pub fn check(&self, ctx: &str) -> Result<(), Error> {
+        self.cash.check(&format!("{} cash", ctx))?;
+        for (g, d) in &self.resources {
+            d.check(&format!("{} {}", ctx, g))?;
+        }
+        for (g, d) in &self.skills {
+            d.check(&format!("{} {} skill", ctx, g))?;
+        }
+        Ok(())
+    }
+
+    /// One agent drawn from the population, eating by `preferences`
+    pub fn agent(&self, goods: &Goods, id: AgentId, home: RegionId, preferences: &Preferences,
+                 rng: &mut impl Rng) -> Agent {
+        let cash = Money(self.cash.sample(rng));
+        // sample in registry order so a seeded rng gives the same agents every time
+        let res = goods.all().iter()
+            .map(|g| (*g, Quantity(self.resources.get(g).map_or(0, |d| d.sample(rng)))))
+            .collect();
+        let skill = goods.all().iter()
+            .map(|g| (*g, self.skills.get(g).map_or(1.0, |d| d.sample(rng))))
+            .collect();
+        let mut a = Agent::new_with_id(id, cash, res, skill);
+        a.home = home;
+        a.strategy = self.strategy.build(rng);
+        a.hours = self.hours;
+        a.preferences = preferences.clone();
+        a
+    }
+}
+
+impl MerchantDef {
+    pub fn generate(&self, goods: &Goods, home: RegionId, prices: &HashMap<Good, Money>, agents: &mut Agents) -> Result<(), Error> {
+        let fair: HashMap<_, _> = self.stock.keys().map(|g| (*g, prices[g])).collect();
+        for _ in 0..self.count {
+            let res = goods.all().iter()
+                .map(|g| (*g, self.stock.get(g).cloned().unwrap_or(Quantity::ZERO)))
+                .collect();
+            let skill = goods.all().iter().map(|g| (*g, 1.0)).collect();
+            let id = Agent::new_into_map(agents, Money(self.cash), res, skill)?;
+            let merchant = Merchant::new(self.spread, self.risk, self.depth, self.smoothing,
+                                         self.stock.clone(), fair.clone());
+            let a = agents.get_mut(&id).unwrap();
+            a.home = home;
+            a.strategy = Strategy::Merchant(merchant);
+            // never produces or works for others
+            a.hours = 0;
+        }
+        Ok(())
+    }
+}
+
+impl TraderDef {
+    pub fn generate(&self, goods: &Goods, home: RegionId, agents: &mut Agents) -> Result<(), Error> {
+        for _ in 0..self.count {
+            let res = goods.all().iter().map(|g| (*g, Quantity::ZERO)).collect();
+            let skill = goods.all().iter().map(|g| (*g, 1.0)).collect();
+            let id = Agent::new_into_map(agents, Money(self.cash), res, skill)?;
+            let a = agents.get_mut(&id).unwrap();
+            a.home = home;
+            a.strategy = Strategy::Trader(Trader::new(self.capacity, self.min_margin, self.markdown));
+            a.hours = 0;
+        }
+        Ok(())
+    }
+}
+
+impl FirmDef {
+    /// Owners are drawn from the agents of `home` that work
+    pub fn generate(&self, goods: &Goods, home: RegionId, tasks: &[&Task], rng: &mut impl Rng, agents: &mut Agents) -> Result<(), Error> {
+        for _ in 0..self.count {
+            let candidates: Vec<AgentId> = agents.values()
+                .filter(|a| a.home == home && a.hours > 0)
+                .map(|a| a.id)
+                .collect();
+            let owners = candidates.choose_multiple(rng, self.owners).map(|&id| (id, 1)).collect();
+            let res = goods.all().iter()
+                .map(|g| (*g, self.stock.get(g).cloned().unwrap_or(Quantity::ZERO)))
+                .collect();
+            let skill = goods.all().iter().map(|g| (*g, 1.0)).collect();
+            let id = Agent::new_into_map(agents, Money(self.cash), res, skill)?;
+            let a = agents.get_mut(&id).unwrap();
+            a.home = home;
+            a.strategy = Strategy::Firm(Firm::new(tasks, self.batches, owners, self.reserve));
+            a.hours = 0;
+        }
+        Ok(())
+    }
+}
+
+impl ResourceDef {
+    /// Owners, if owned, are drawn from the agents of `home` that work
+    pub fn generate(&self, home: RegionId, rng: &mut impl Rng, agents: &Agents) -> Result<Vec<Resource>, Error> {
+        let candidates: Vec<AgentId> = agents.values()
+            .filter(|a| a.home == home && a.hours > 0)
+            .map(|a| a.id)
+            .collect();
+        let mut resources = Vec::with_capacity(self.count);
+        for _ in 0..self.count {
+            let mut r = Resource::new(self.name.clone(), home, self.capacity, self.regrowth, self.limit);
+            r.stock = self.stock.unwrap_or(self.capacity);
+            if self.owned {
+                match candidates.choose(rng) {
+                    Some(&owner) => r = r.owned_by(owner, self.rent, self.gain),
+                    None => bail!("resource {} has nobody in its region to own it", self.name),
+                }
+            }
+            resources.push(r);
+        }
+        Ok(resources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BREAD: &str = r#"
+        ticks = 10
+
+        [[goods]]
+        name = "Food"
+        [[goods]]
+        name = "Grain"
+
+        [[tasks]]
+        name = "Bake"
+        inputs = { Grain = 25 }
+        output = { Food = 10 }
+        [[tasks]]
+        name = "Farm"
+        output = { Grain = 10 }
+
+        [[population]]
+        count = 4
+        cash = { uniform = [100, 500] }
+        resources = { Grain = { fixed = 20 }, Food = { uniform = [2, 15] } }
+        skills = { Food = { choice = [0.5, 2.0] } }
+
+        [market]
+        prices = { Food = 25, Grain = 5 }
+        price_rules = { Food = { pid = { kp = 0.2, ki = 0.05, kd = 0.1 } } }
+
+        [consumption]
+        good = "Food"
+        mu = [120, 60, 50]
+        discount = 0.8
+    "#;
+
+    #[test]
+    fn build_from_toml() {
+        let mut scenario = Scenario::parse(BREAD, "toml").unwrap();
+        let sim = scenario.build().unwrap();
+        let (food, grain) = (sim.goods().get("Food").unwrap(), sim.goods().get("Grain").unwrap());
+
+        assert_eq!(sim.agents().len(), 4);
+        assert_eq!(sim.tasks()[0].inputs.as_slice(), &[(grain, Quantity(25))]);
+        assert_eq!(sim.trade_rounds(), 2);
+        for a in sim.agents().values() {
+            assert!(a.cash >= Money(100) && a.cash < Money(500));
+            assert_eq!(a.res[&grain], Quantity(20));
+            assert_eq!(a.skill[&grain], 1.0);
+            assert!(a.skill[&food] == 0.5 || a.skill[&food] == 2.0);
+        }
+
+        scenario.market.price_rules.insert(food, PriceRule::Smoothed { gain: 0.25, window: 0, decay: 0.7 });
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn formats_agree() {
+        let scenario = Scenario::parse(BREAD, "toml").unwrap();
+        let json = serde_json::to_string(&scenario).unwrap();
+        let from_json = Scenario::parse(&json, "json").unwrap();
+        let ron = ron::ser::to_string(&scenario).unwrap();
+        let from_ron = Scenario::parse(&ron, "ron").unwrap();
+
+        assert_eq!(from_json.tasks[0].inputs, scenario.tasks[0].inputs);
+        assert_eq!(from_ron.market.prices, scenario.market.prices);
+    }
+
+    #[test]
+    fn merchants() {
+        let scenario = Scenario::parse(&format!("{}{}", BREAD, r#"
+            [[merchants]]
+            count = 2
+            cash = 1000
+            stock = { Food = 30 }
+            spread = 0.1
+            depth = 5
+        "#), "toml").unwrap();
+        let sim = scenario.build().unwrap();
+        let (food, grain) = (sim.goods().get("Food").unwrap(), sim.goods().get("Grain").unwrap());
+
+        let merchants: Vec<_> = sim.agents().values().filter_map(|a| a.strategy.merchant().map(|m| (a, m))).collect();
+        assert_eq!(merchants.len(), 2);
+        for (a, m) in merchants {
+            assert_eq!(a.cash, Money(1000));
+            assert_eq!((a.res[&food], a.res[&grain]), (Quantity(30), Quantity::ZERO));
+            assert_eq!(m.fair[&food], Money(25));
+            assert!(m.quotes(grain, Quantity::ZERO).is_none());
+        }
+    }
+
+    #[test]
+    fn mixed_strategies() {
+        let mut scenario = Scenario::parse(BREAD, "toml").unwrap();
+        let mut farmers = scenario.population[0].clone();
+        farmers.strategy = StrategyDef::Specialist { task: "Farm".into() };
+        scenario.population.push(farmers);
+        let sim = scenario.build().unwrap();
+
+        let specialists = sim.agents().values()
+            .filter(|a| a.strategy == Strategy::Specialist(Specialist { task: "Farm".into() }))
+            .count();
+        assert_eq!((sim.agents().len(), specialists), (8, 4));
+
+        scenario.population[1].strategy = StrategyDef::Specialist { task: "Fish".into() };
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn regions() {
+        let mut scenario = Scenario::parse(&format!("{}{}", BREAD, r#"
+            [[regions]]
+            name = "Village"
+            [[regions]]
+            name = "City"
+            prices = { Food = 40 }
+            [[routes]]
+            from = "Village"
+            to = "City"
+            cost = 2
+            delay = 3
+        "#), "toml").unwrap();
+        scenario.population[0].region = Some("City".into());
+        let sim = scenario.build().unwrap();
+        let food = sim.goods().get("Food").unwrap();
+
+        assert_eq!(sim.regions().len(), 2);
+        assert_eq!(sim.regions()[0].market.price(food), Money(25));
+        assert_eq!(sim.regions()[1].market.price(food), Money(40));
+        assert_eq!(sim.transport().routes().len(), 2);
+        assert!(sim.agents().values().all(|a| a.home == 1));
+
+        scenario.population[0].region = Some("Port".into());
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn production_chain() {
+        let sim = Scenario::load("scenarios/mill.toml").unwrap().build().unwrap();
+        let g = |name| sim.goods().get(name).unwrap();
+        let mill = sim.tasks().iter().find(|t| t.name == "Mill").unwrap();
+        let farm = sim.tasks().iter().find(|t| t.name == "Farm").unwrap();
+
+        assert_eq!(mill.tools.as_slice(), &[Tool { good: g("Millstone"), qty: Quantity(1), life: 10 }]);
+        assert_eq!(farm.byproducts.as_slice(), &[(g("Straw"), Quantity(4))]);
+
+        let mut scenario = Scenario::load("scenarios/mill.toml").unwrap();
+        scenario.tasks[1].tools.get_mut(&g("Millstone")).unwrap().life = 0;
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn working_day() {
+        let mut scenario = Scenario::load("scenarios/bread_labor.toml").unwrap();
+        let sim = scenario.build().unwrap();
+
+        assert_eq!(sim.tasks().iter().map(|t| t.labor).collect::<Vec<_>>(), [4, 2]);
+        assert!(sim.agents().values().all(|a| a.hours == 8));
+        assert_eq!(sim.labor(), &[LaborMarket::new(Money(5), 0.1)]);
+
+        scenario.labor = None;
+        assert!(scenario.build().unwrap().labor().is_empty());
+        scenario.tasks[0].labor = 0;
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn firms() {
+        let mut scenario = Scenario::load("scenarios/bread_firms.toml").unwrap();
+        let sim = scenario.build().unwrap();
+        let grain = sim.goods().get("Grain").unwrap();
+
+        let firms: Vec<_> = sim.agents().values().filter_map(|a| a.strategy.firm().map(|f| (a, f))).collect();
+        assert_eq!(firms.len(), 1);
+        let (a, firm) = firms[0];
+        assert_eq!((a.cash, a.res[&grain], a.hours), (Money(1000), Quantity(100), 0));
+        assert_eq!(firm.targets[&grain], Quantity(100));
+        assert_eq!(firm.owners.len(), 3);
+        assert!(firm.owners.keys().all(|id| sim.agents()[id].strategy == Strategy::default()));
+
+        scenario.labor = None;
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn demography() {
+        let mut scenario = Scenario::load("scenarios/bread_families.toml").unwrap();
+        let sim = scenario.build().unwrap();
+        let d = sim.demography().unwrap();
+        assert_eq!(d.estates, Estates::Auctioned);
+        assert_eq!(d.immigration.as_ref().map(|im| im.population.count), Some(2));
+
+        scenario.demography.as_mut().unwrap().immigration.as_mut().unwrap().population.region = Some("Port".into());
+        assert!(scenario.build().is_err());
+        // a child can't be given more than its parent has
+        let d = scenario.demography.as_mut().unwrap();
+        d.immigration = None;
+        let valid = d.births.unwrap();
+        assert!(scenario.build().is_ok());
+        for births in [Births { share: 1.5, ..valid }, Births { rate: -0.1, ..valid }, Births { mutation: -1., ..valid }] {
+            scenario.demography.as_mut().unwrap().births = Some(births);
+            assert!(scenario.build().is_err());
+        }
+    }
+
+    #[test]
+    fn basket() {
+        let mut scenario = Scenario::load("scenarios/bread_and_fish.toml").unwrap();
+        scenario.seed = Some(1);
+        let sim = scenario.build().unwrap();
+        let fish = sim.goods().get("Fish").unwrap();
+        let eats = |a: &Agent| a.preferences.utility.goods().len();
+        assert_eq!(sim.agents().values().filter(|a| eats(a) == 2).count(), 15);
+        assert_eq!(sim.agents().values().filter(|a| eats(a) == 1).count(), 3);
+
+        // each agent values fish by its own tastes and what it holds, the market's value is ignored
+        let a = &sim.agents()[&0];
+        let nothing = crate::agent::MU(vec![(Money::ZERO, 0)]);
+        assert_eq!(a.values(&nothing, fish).into_owned().0, a.preferences.mu(fish, &a.res).0);
+        assert!(a.diet().unwrap().iter().all(|&(g, amt)| amt <= a.res[&g]));
+
+        // a single good or a utility, not both
+        scenario.consumption.good = Some(fish);
+        assert!(scenario.build().is_err());
+        scenario.consumption.good = None;
+        scenario.consumption.max = Quantity::ZERO;
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn hunger() {
+        let mut scenario = Scenario::load("scenarios/bread_hunger.toml").unwrap();
+        let sim = scenario.build().unwrap();
+        assert_eq!(sim.health().map(|h| h.need), Some(Quantity(2)));
+
+        scenario.consumption.health.as_mut().unwrap().loss = 0.;
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn land() {
+        let mut scenario = Scenario::load("scenarios/bread_land.toml").unwrap();
+        scenario.seed = Some(1);
+        let sim = scenario.build().unwrap();
+        let fields = sim.land().resources();
+        assert_eq!(fields.len(), 3);
+        assert!(fields[..2].iter().all(|f| f.owner.is_some_and(|o| sim.agents().contains_key(&o))));
+        assert_eq!(fields[2].owner, None);
+
+        // farming a resource nobody defined
+        scenario.tasks[1].draws.remove("Field");
+        scenario.tasks[1].draws.insert("Forest".into(), Quantity(10));
+        assert!(scenario.build().is_err());
+        scenario.tasks[1].draws.clear();
+        scenario.resources[0].limit = Quantity::ZERO;
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn distributions() {
+        let mut scenario = Scenario::parse(BREAD, "toml").unwrap();
+        scenario.population[0].cash = Dist::Uniform([300, 300]);
+        let sim = scenario.build().unwrap();
+        assert!(sim.agents().values().all(|a| a.cash == Money(300)));
+
+        scenario.population[0].cash = Dist::Uniform([300, 200]);
+        assert!(scenario.build().is_err());
+        scenario.population[0].cash = Dist::Choice(vec![]);
+        assert!(scenario.build().is_err());
+    }
+
+    #[test]
+    fn undefined_good() {
+        let mut scenario = Scenario::parse(BREAD, "toml").unwrap();
+        scenario.goods.pop();
+        assert!(scenario.build().is_err());
+
+        let e = Scenario::parse(&BREAD.replace("output = { Grain", "output = { Grian"), "toml").unwrap_err();
+        assert!(e.to_string().contains("undefined good Grian"), "{}", e);
+    }
+}
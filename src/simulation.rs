@@ -0,0 +1,623 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::agent::{Agent, AgentId, Agents, Health, MU};
+use crate::demography::{Demography, Estates};
+use crate::goods::{Good, Goods, Task};
+use crate::labor::LaborMarket;
+use crate::land::Land;
+use crate::ledger::{Entry, Ledger, Totals};
+use crate::market::{transfer_cash, GoodMap, Market, UnexecutedTrades};
+use crate::record::{add, register, set_tick};
+use crate::region::{Region, RegionId, Transport};
+use crate::units::{Money, Quantity};
+use tracing::{debug, debug_span, info, info_span, trace, trace_span, warn};
+
+/// A running economy, usually built from a `Scenario`. Each `step` is one tick.
+pub struct Simulation {
+    goods: Goods,
+    tasks: Vec<Task>,
+    agents: Agents,
+    /// at least one, each with its own market
+    regions: Vec<Region>,
+    transport: Transport,
+    /// one per region, none if agents can't hire each other
+    labor: Vec<LaborMarket>,
+    /// births, estates and immigration, none if agents only ever die
+    demography: Option<Demography>,
+    /// resources tasks draw on, empty if goods can be made from nothing
+    land: Land,
+    /// id of the next agent born or arriving, ids of the dead aren't reused
+    next_id: AgentId,
+    /// hunger that kills over several ticks, without it an agent dies as soon as it holds
+    /// a unit or less of what it eats before eating
+    health: Option<Health>,
+    trade_rounds: u8,
+    horizon: u16,
+    tick: u16,
+    seed: u64,
+    ledger: Ledger,
+    /// verify the ledger accounts for every change in total cash and goods after each tick
+    check_invariants: bool,
+}
+
+impl Simulation {
+    pub fn new(goods: Goods,
+               tasks: Vec<Task>,
+               agents: Agents,
+               regions: Vec<Region>,
+               trade_rounds: u8,
+               horizon: u16,
+               seed: u64) -> Simulation {
+        // no one is born or arrives once the ids run out
+        let next_id = Agent::next_id(&agents).unwrap_or(AgentId::MAX);
+        Simulation {
+            goods,
+            tasks,
+            agents,
+            regions,
+            transport: Transport::default(),
+            labor: Vec::new(),
+            demography: None,
+            land: Land::default(),
+            next_id,
+            health: None,
+            trade_rounds,
+            horizon,
+            tick: 0,
+            seed,
+            ledger: Ledger::default(),
+            check_invariants: false,
+        }
+    }
+
+    /// Register the csv records written by `step`, call after `record::init_recorder`
+    pub fn register_records(&self) {
+        register("deaths", &["agent_id"]);
+        register("tasks", &["task_name", "task_value", "revenue", "cost", "agent_id"]);
+        register("price", &["region", "good", "new_price", "old_price", "unexecuted", "volume", "rejected"]);
+        let mut agent_cols = vec!["agent_id".to_string(), "cash".to_string()];
+        agent_cols.extend(self.goods.all().iter().map(|g| g.name().to_lowercase()));
+        register("agent_info", &agent_cols.iter().map(String::as_str).collect::<Vec<_>>());
+        register("utility", &["agent_id", "utility", "food_consumed"]);
+        register("trades", &["good", "price", "supply", "to_trade", "agent_id"]);
+        register("ledger", &["kind", "agent", "other", "good", "amt", "price"]);
+        register("quotes", &["agent_id", "good", "fair", "bid", "ask", "stock"]);
+        register("wages", &["region", "wage", "hired", "unhired"]);
+        register("births", &["agent_id", "parent"]);
+        register("health", &["agent_id", "health"]);
+        register("spoilage", &["good", "spoiled", "storage"]);
+        register("land", &["resource", "name", "region", "owner", "stock", "drawn", "rent"]);
+    }
+
+    /// Run `n` ticks
+    pub fn run(&mut self, n: u16) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+
+    /// Run until the scenario's horizon is reached
+    pub fn run_to_end(&mut self) {
+        while self.tick < self.horizon {
+            self.step();
+        }
+    }
+
+    /// Run one tick: arrivals, trade rounds, departures, consumption and hunger, deaths,
+    /// production, hiring, dividends, spoilage and storage, regrowth of the land, then
+    /// births and immigration.
+    /// Panics if invariant checks are on and the tick changed total cash or goods
+    /// in a way the ledger doesn't account for
+    pub fn step(&mut self) {
+        set_tick(self.tick);
+        let span = info_span!("tick", tick = self.tick);
+        let _enter = span.enter();
+        self.ledger.set_tick(self.tick);
+        let since = self.ledger.len();
+        let before = if self.check_invariants { Some(Totals::of(&self.agents)) } else { None };
+
+        self.transport.deliver(&mut self.agents, self.tick, &mut self.ledger);
+        for round in 0..self.trade_rounds {
+            let span = debug_span!("trade_round", round);
+            let _enter = span.enter();
+            self.trade_round();
+        }
+        self.travel();
+        self.consume();
+        self.produce();
+        self.pay_dividends();
+        self.spoil();
+        self.regrow();
+        self.grow();
+        info!(agents = self.agents.len(), "tick done");
+
+        self.ledger.record(since);
+        if let Some(before) = before {
+            if let Err(e) = self.ledger.verify(since, &before, &self.agents) {
+                panic!("ledger invariant broken: {}", e);
+            }
+        }
+
+        for a in self.agents.values() {
+            let mut row = vec![a.id as i64, a.cash.0];
+            row.extend(self.goods.all().iter().map(|g| a.res[g].0 as i64));
+            add("agent_info", row);
+            if let Some(m) = a.strategy.merchant() {
+                for &good in self.goods.all() {
+                    let stock = a.res[&good];
+                    if let Some((bid, ask)) = m.quotes(good, stock) {
+                        add("quotes", (a.id, good, m.fair[&good], bid, ask, stock));
+                    }
+                }
+            }
+        }
+        self.tick += 1;
+    }
+
+    fn trade_round(&mut self) {
+        for (r, region) in self.regions.iter_mut().enumerate() {
+            let span = debug_span!("region", region = %region.name);
+            let _enter = span.enter();
+            let market = &mut *region.market;
+            let mut local = take_local(&mut self.agents, r as RegionId, &self.transport);
+
+            // register trades
+            for &good in self.goods.all() {
+                let span = debug_span!("good", %good);
+                let _enter = span.enter();
+                // agents that eat it value it by their own preferences instead
+                let mut mu = MU::from_market(market, &self.tasks, good);
+                let def = self.goods.def(good);
+                if def.perishes() {
+                    mu = mu.kept(def.decay, def.storage);
+                }
+                market.collect_orders(&local, good, &mu);
+            }
+            let res = market.execute_trades(&mut local, &mut self.ledger);
+            for (&good, t) in &res {
+                let span = debug_span!("good", %good);
+                let _enter = span.enter();
+                debug!(price = %market.price(good), unexecuted = ?t, "cleared");
+            }
+            log_prices(&region.name, &res, market);
+
+            for a in local.values_mut() {
+                let brain = a.strategy.brain_mut();
+                for &good in self.goods.all() {
+                    brain.observe(good, market.price(good));
+                }
+            }
+            self.agents.append(&mut local);
+        }
+    }
+
+    /// Traders at home mark down unsold cargo, or if they've sold out, buy whatever fetches
+    /// most elsewhere and set off with it
+    fn travel(&mut self) {
+        let ids: Vec<AgentId> = self.agents.values()
+            .filter(|a| a.strategy.trader().is_some() && !self.transport.travelling(a.id))
+            .map(|a| a.id)
+            .collect();
+        for id in ids {
+            let span = trace_span!("agent", id);
+            let _enter = span.enter();
+            let a = self.agents.get_mut(&id).unwrap();
+            let trader = a.strategy.trader_mut().unwrap();
+            if trader.has_cargo(&a.res) {
+                trader.mark_down();
+                continue;
+            }
+            let home = a.home;
+            let plan = trader.plan(home, a.cash, self.transport.routes(), &self.regions, self.goods.all());
+            let (route, good, qty) = match plan {
+                Some(p) => p,
+                None => continue,
+            };
+            let market = &mut *self.regions[home as usize].market;
+            let fill = match market.buy(&mut self.agents, id, good, qty, &mut self.ledger) {
+                Ok(fill) if fill.qty > Quantity::ZERO => fill,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!(%good, "buying cargo failed: {}", e);
+                    continue;
+                }
+            };
+            let cost = fill.value.saturating_add(route.cost.saturating_mul(fill.qty));
+            self.agents.get_mut(&id).unwrap().strategy.trader_mut().unwrap().bought(good, fill.qty, cost);
+            // unshipped cargo is sold back at home
+            if let Err(e) = self.transport.ship(&mut self.agents, id, good, fill.qty, route,
+                                                self.tick, &mut self.ledger) {
+                warn!(%good, "shipping failed: {}", e);
+            }
+        }
+    }
+
+    fn consume(&mut self) {
+        let mut dead = BTreeSet::new();
+
+        for a in self.agents.values_mut() {
+            let span = trace_span!("agent", id = a.id);
+            let _enter = span.enter();
+            let stock = a.food();
+            let diet = match a.diet() {
+                Some(diet) => diet,
+                None => continue,
+            };
+            let consumption: Quantity = diet.iter().map(|&(_, amt)| amt).sum();
+            match &self.health {
+                Some(h) => {
+                    a.health = h.after(a.health, consumption);
+                    add("health", (a.id, a.health));
+                    if a.health <= 0. {
+                        dead.insert(a.id);
+                    }
+                }
+                None if stock <= Quantity(1) => {
+                    dead.insert(a.id);
+                }
+                None => {}
+            }
+            add("utility", (a.id, a.preferences.utility.of(&diet.iter().cloned().collect()), consumption));
+            for (food, amt) in diet {
+                *a.res.get_mut(&food).unwrap() -= amt;
+                self.ledger.consume(a.id, food, amt);
+                debug!(good = %food, consumption = %amt, left = %a.res[&food], health = a.health, "ate");
+            }
+        }
+
+        // remove dead agents, after settling their estates
+        for a in &dead {
+            let mut heir = None;
+            if let Some(d) = &mut self.demography {
+                let market = &mut *self.regions[self.agents[a].home as usize].market;
+                d.settle(&mut self.agents, *a, market, &mut self.ledger);
+                if d.estates != Estates::Lost {
+                    heir = d.heirs(&self.agents, *a).first().cloned();
+                }
+            }
+            // the eldest heir gets its land, common land if it has none
+            self.land.bequeath(*a, heir);
+            if let Some(agent) = self.agents.remove(a) {
+                let mut res: Vec<_> = agent.res.into_iter().filter(|&(_, amt)| amt != Quantity::ZERO).collect();
+                res.sort();
+                self.ledger.push(Entry::Death { agent: *a, cash: agent.cash, res });
+            }
+            info!(agent = a, "died");
+            add("deaths", a)
+        }
+    }
+
+    // agents choose what to produce and produce it, until their hours run out,
+    // then hire out the hours they have left
+    fn produce(&mut self) {
+        let ids: Vec<AgentId> = self.agents.keys().cloned().collect();
+        for id in ids {
+            let span = trace_span!("agent", id);
+            let _enter = span.enter();
+            let a = self.agents.get_mut(&id).unwrap();
+            a.hours_left = a.hours;
+            loop {
+                let a = &self.agents[&id];
+                let market = &mut *self.regions[a.home as usize].market;
+                let tasks = self.land.open(&self.tasks, a);
+                let task = match a.choose_task(&tasks, market) {
+                    Some(task) => task,
+                    None => break,
+                };
+                if let Some(labor) = self.labor.get(a.home as usize) {
+                    if a.strategy.brain().prefers_wage(a, task, market, labor.wage) {
+                        debug!(task = %task.name, wage = %labor.wage, "working for wages instead");
+                        break;
+                    }
+                }
+                if let Ok(value) = task.value(market, a.productivity(task.output.0)) {
+                    add("tasks", (&task.name, value, id));
+                }
+                Agent::buy_inputs(&mut self.agents, id, task, market, &mut self.ledger);
+                // only draw on the land once everything else the task needs is in hand
+                let a = &self.agents[&id];
+                let ready = task.needs().all(|(g, amt)| a.res[&g] >= amt);
+                let drawn = !ready || self.land.draw(&mut self.agents, id, task, &mut self.ledger);
+                let a = self.agents.get_mut(&id).unwrap();
+                let performed = drawn && a.perform_task(task, &mut self.ledger);
+                let profit = if performed {
+                    task.value(market, a.productivity(task.output.0)).map_or(Money::ZERO, |(profit, _, _)| profit)
+                } else {
+                    Money::ZERO
+                };
+                a.strategy.brain_mut().learn(task, profit);
+                if !performed {
+                    break;
+                }
+                a.hours_left = a.hours_left.saturating_sub(task.labor.max(1));
+            }
+        }
+        self.hire();
+    }
+
+    fn hire(&mut self) {
+        for (r, labor) in self.labor.iter_mut().enumerate() {
+            let transport = &self.transport;
+            let local: Vec<&Agent> = self.agents.values()
+                .filter(|a| a.home as usize == r && !transport.travelling(a.id))
+                .collect();
+            let employers: Vec<AgentId> = local.iter().map(|a| a.id).collect();
+            let workers: Vec<AgentId> = local.iter().filter(|a| a.hours > 0).map(|a| a.id).collect();
+            let wage = labor.wage;
+            // hired hands only work tasks that don't draw on land
+            let tasks: Vec<Task> = self.tasks.iter().filter(|t| t.draws.is_none()).cloned().collect();
+            let (hired, unhired) = labor.hire(&mut self.agents, &workers, &employers, &tasks,
+                                              &*self.regions[r].market, &mut self.ledger);
+            add("wages", (&self.regions[r].name, wage, hired, unhired));
+        }
+    }
+
+    // agents are born and arrive
+    fn grow(&mut self) {
+        let d = match &mut self.demography {
+            Some(d) => d,
+            None => return,
+        };
+        let born = d.grow(&mut self.agents, &self.goods, &mut self.next_id, &mut self.ledger);
+        for (id, parent) in born {
+            info!(agent = id, ?parent, "born");
+            add("births", (id, parent.map_or(-1, |p| p as i32)));
+        }
+    }
+
+    // goods held go bad and cost to store, agents pay what they can
+    fn spoil(&mut self) {
+        for &good in self.goods.all() {
+            let def = self.goods.def(good);
+            if !def.perishes() {
+                continue;
+            }
+            let (mut spoiled, mut storage) = (Quantity::ZERO, Money::ZERO);
+            for a in self.agents.values_mut() {
+                let lost = a.spoil(good, def.decay);
+                if lost > Quantity::ZERO {
+                    self.ledger.push(Entry::Spoil { agent: a.id, good, amt: lost });
+                    spoiled += lost;
+                }
+                let cost = def.storage.saturating_mul(a.res[&good]).min(a.cash).max(Money::ZERO);
+                if cost > Money::ZERO {
+                    a.cash -= cost;
+                    self.ledger.push(Entry::Storage { agent: a.id, good, cost });
+                    storage += cost;
+                }
+            }
+            debug!(%good, %spoiled, %storage, "spoiled");
+            add("spoilage", (good, spoiled, storage));
+        }
+    }
+
+    // resources regrow and rents move with how much land was wanted
+    fn regrow(&mut self) {
+        for (i, r) in self.land.resources().iter().enumerate() {
+            add("land", (i, &r.name, r.region, r.owner.map_or(-1, |o| o as i32), r.stock, r.drawn, r.rent));
+        }
+        self.land.grow();
+    }
+
+    // firms pay out what they don't keep in reserve
+    fn pay_dividends(&mut self) {
+        let ids: Vec<AgentId> = self.agents.keys().cloned().collect();
+        for id in ids {
+            let a = &self.agents[&id];
+            let dividends = match a.strategy.firm() {
+                Some(firm) => firm.dividends(a.cash, |owner| self.agents.contains_key(&owner)),
+                None => continue,
+            };
+            for (owner, amt) in dividends {
+                if let Err(e) = transfer_cash(&mut self.agents, id, owner, amt) {
+                    warn!(firm = id, owner, "dividend not paid: {}", e);
+                    continue;
+                }
+                self.ledger.push(Entry::Dividend { firm: id, owner, amt });
+                debug!(firm = id, owner, %amt, "paid dividend");
+            }
+        }
+    }
+
+    pub fn goods(&self) -> &Goods {
+        &self.goods
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn agents(&self) -> &Agents {
+        &self.agents
+    }
+
+    pub fn agents_mut(&mut self) -> &mut Agents {
+        &mut self.agents
+    }
+
+    /// Market of the first region, the only one unless the scenario defines regions
+    pub fn market(&self) -> &dyn Market {
+        &*self.regions[0].market
+    }
+
+    pub fn market_mut(&mut self) -> &mut dyn Market {
+        &mut *self.regions[0].market
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    /// Replace the routes between regions, any shipments in transit are dropped
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    /// Let agents hire each other, at the wages of one labor market per region
+    pub fn set_labor(&mut self, labor: Vec<LaborMarket>) {
+        self.labor = labor;
+    }
+
+    pub fn labor(&self) -> &[LaborMarket] {
+        &self.labor
+    }
+
+    pub fn set_demography(&mut self, demography: Demography) {
+        self.demography = Some(demography);
+    }
+
+    pub fn demography(&self) -> Option<&Demography> {
+        self.demography.as_ref()
+    }
+
+    /// Every transfer, production, consumption and death so far
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn check_invariants(&self) -> bool {
+        self.check_invariants
+    }
+
+    /// Verify conservation of cash and goods after every tick, off by default
+    pub fn set_check_invariants(&mut self, on: bool) {
+        self.check_invariants = on;
+    }
+
+    /// Let hunger build up over ticks, instead of killing agents as soon as they run out of food
+    pub fn set_health(&mut self, health: Health) {
+        self.health = Some(health);
+    }
+
+    pub fn health(&self) -> Option<Health> {
+        self.health
+    }
+
+    pub fn set_land(&mut self, land: Land) {
+        self.land = land;
+    }
+
+    pub fn land(&self) -> &Land {
+        &self.land
+    }
+
+    pub fn trade_rounds(&self) -> u8 {
+        self.trade_rounds
+    }
+
+    /// Number of ticks the scenario is meant to run for
+    pub fn horizon(&self) -> u16 {
+        self.horizon
+    }
+
+    /// Number of ticks run so far
+    pub fn tick(&self) -> u16 {
+        self.tick
+    }
+
+    /// Master seed the simulation was built from, building the same scenario
+    /// with this seed replays the run exactly
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+/// Move the agents trading in region `r` out of `agents`: those living there and not on the road
+fn take_local(agents: &mut Agents, r: RegionId, transport: &Transport) -> Agents {
+    let ids: Vec<AgentId> = agents.values()
+        .filter(|a| a.home == r && !transport.travelling(a.id))
+        .map(|a| a.id)
+        .collect();
+    ids.into_iter().map(|id| (id, agents.remove(&id).unwrap())).collect()
+}
+
+fn log_prices(region: &str, res: &GoodMap<UnexecutedTrades>, market: &dyn Market) {
+    for (&good, &t) in res {
+        let (un, vol) = match t {
+            UnexecutedTrades::Sells(un, vol, _) => (-un, vol),
+            UnexecutedTrades::Buys(un, vol, _) => (un, vol),
+            UnexecutedTrades::All(vol, _) => (Quantity::ZERO, vol)
+        };
+        add("price", (region, good, market.price(good), market.old_price(good), un, vol, t.rejected()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::scenario::{self, Scenario};
+    use crate::units::{Money, Quantity};
+
+    #[test]
+    fn step_and_run() {
+        let mut sim = scenario::load("scenarios/bread.toml").unwrap();
+        let alive = sim.agents().len();
+
+        sim.step();
+        assert_eq!(sim.tick(), 1);
+        sim.run(4);
+        assert_eq!(sim.tick(), 5);
+        assert!(sim.agents().len() <= alive);
+
+        sim.run_to_end();
+        assert_eq!(sim.tick(), sim.horizon());
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let mut scenario = Scenario::load("scenarios/bread.toml").unwrap();
+        scenario.seed = Some(7);
+        let mut a = scenario.build().unwrap();
+        let mut b = scenario.build().unwrap();
+        assert_eq!(a.agents(), b.agents());
+
+        for _ in 0..20 {
+            a.step();
+            b.step();
+            assert_eq!(a.agents(), b.agents());
+            for &good in a.goods().all() {
+                assert_eq!(a.market().price(good), b.market().price(good));
+            }
+        }
+    }
+
+    #[test]
+    fn ledger_accounts_for_everything() {
+        let mut paths: Vec<_> = std::fs::read_dir("scenarios").unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in &paths {
+            let mut scenario = Scenario::load(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            scenario.seed = Some(3);
+            scenario.check_invariants = true;
+            let mut sim = scenario.build().unwrap();
+            let mut alive: BTreeSet<_> = sim.agents().keys().cloned().collect();
+            let mut dead = BTreeSet::new();
+            for _ in 0..20 {
+                // panics on the first tick the ledger can't account for
+                sim.step();
+                for a in sim.agents().values() {
+                    assert!(a.cash >= Money::ZERO && a.res.values().all(|&q| q >= Quantity::ZERO), "{:?}", a);
+                    assert!(a.health > 0. && a.health <= 1., "{:?}", a);
+                }
+                for f in sim.land().resources() {
+                    assert!(f.stock >= Quantity::ZERO && f.stock <= f.capacity, "{:?}", f);
+                }
+                // newcomers never take the id of someone who died
+                let now: BTreeSet<_> = sim.agents().keys().cloned().collect();
+                assert!(now.is_disjoint(&dead), "{}", path.display());
+                dead.extend(alive.difference(&now));
+                alive = now;
+            }
+            assert!(!sim.ledger().is_empty());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::path::Path;
+
+use failure::Error;
+
+/// Headline numbers of a recorded run, read back from its csv files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub ticks: u16,
+    pub prices: Vec<PriceSummary>,
+    pub deaths: usize,
+    pub survivors: usize,
+    pub mean_cash: f64,
+}
+
+/// Price series of one good in one region
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceSummary {
+    /// empty for runs recorded before regions existed
+    pub region: String,
+    pub good: String,
+    pub first: i64,
+    pub last: i64,
+    pub min: i64,
+    pub max: i64,
+    pub mean: f64,
+    pub volume: i64,
+}
+
+#[derive(Deserialize)]
+struct PriceRow {
+    tick: u16,
+    #[serde(default)]
+    region: String,
+    good: String,
+    new_price: i64,
+    volume: i64,
+}
+
+#[derive(Deserialize)]
+struct AgentRow {
+    tick: u16,
+    cash: i64,
+}
+
+impl Summary {
+    pub fn load(dir: impl AsRef<Path>) -> Result<Summary, Error> {
+        let dir = dir.as_ref();
+
+        let mut ticks = 0;
+        let mut prices: Vec<(PriceSummary, usize)> = Vec::new();
+        for row in csv::Reader::from_path(dir.join("price.csv"))?.deserialize() {
+            let row: PriceRow = row?;
+            ticks = ticks.max(row.tick + 1);
+            match prices.iter_mut().find(|(p, _)| p.good == row.good && p.region == row.region) {
+                Some((p, n)) => {
+                    p.last = row.new_price;
+                    p.min = p.min.min(row.new_price);
+                    p.max = p.max.max(row.new_price);
+                    p.mean += row.new_price as f64;
+                    p.volume += row.volume;
+                    *n += 1;
+                }
+                None => prices.push((PriceSummary {
+                    region: row.region,
+                    good: row.good,
+                    first: row.new_price,
+                    last: row.new_price,
+                    min: row.new_price,
+                    max: row.new_price,
+                    mean: row.new_price as f64,
+                    volume: row.volume,
+                }, 1)),
+            }
+        }
+        let prices = prices.into_iter()
+            .map(|(mut p, n)| {
+                p.mean /= n as f64;
+                p
+            })
+            .collect();
+
+        let deaths = csv::Reader::from_path(dir.join("deaths.csv"))?.records().count();
+
+        let agents = csv::Reader::from_path(dir.join("agent_info.csv"))?
+            .deserialize()
+            .collect::<Result<Vec<AgentRow>, _>>()?;
+        // agents alive at the end of the run, none if everyone died before it
+        let alive: Vec<_> = agents.iter().filter(|a| a.tick + 1 == ticks).collect();
+        let mean_cash = if alive.is_empty() {
+            0.
+        } else {
+            alive.iter().map(|a| a.cash as f64).sum::<f64>() / alive.len() as f64
+        };
+
+        Ok(Summary { ticks, prices, deaths, survivors: alive.len(), mean_cash })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ticks: {}, deaths: {}, survivors: {}, mean cash: {:.1}",
+                 self.ticks, self.deaths, self.survivors, self.mean_cash)?;
+        writeln!(f, "{:<12}{:>8}{:>8}{:>8}{:>8}{:>10}{:>10}",
+                 "good", "first", "last", "min", "max", "mean", "volume")?;
+        // goods are labelled with their region once there's more than one
+        let regions = self.prices.iter().any(|p| p.region != self.prices[0].region);
+        for p in &self.prices {
+            let good = if regions { format!("{}/{}", p.region, p.good) } else { p.good.clone() };
+            writeln!(f, "{:<12}{:>8}{:>8}{:>8}{:>8}{:>10.1}{:>10}",
+                     good, p.first, p.last, p.min, p.max, p.mean, p.volume)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+
+    #[test]
+    fn summarize_run_dir() {
+        let dir = std::env::temp_dir().join("market_sim1_summary_test");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("price.csv"), "tick,good,new_price,old_price,unexecuted,volume\n\
+                                      0,Food,30,25,4,10\n\
+                                      0,Grain,5,5,0,6\n\
+                                      1,Food,20,30,-2,8\n").unwrap();
+        write(dir.join("deaths.csv"), "tick,agent_id\n1,3\n").unwrap();
+        write(dir.join("agent_info.csv"), "tick,agent_id,cash,food,grain\n\
+                                           0,1,100,5,5\n\
+                                           1,1,120,5,5\n\
+                                           1,2,80,5,5\n").unwrap();
+
+        let s = Summary::load(&dir).unwrap();
+        assert_eq!(s.ticks, 2);
+        assert_eq!(s.deaths, 1);
+        assert_eq!(s.survivors, 2);
+        assert_eq!(s.mean_cash, 100.);
+        assert_eq!(s.prices[0], PriceSummary {
+            region: "".into(),
+            good: "Food".into(),
+            first: 30,
+            last: 20,
+            min: 20,
+            max: 30,
+            mean: 25.,
+            volume: 18,
+        });
+    }
+
+    #[test]
+    fn everyone_died() {
+        let dir = std::env::temp_dir().join("market_sim1_summary_dead_test");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("price.csv"), "tick,good,new_price,old_price,unexecuted,volume\n\
+                                      0,Food,30,25,4,10\n\
+                                      1,Food,35,30,4,0\n\
+                                      2,Food,40,35,4,0\n").unwrap();
+        write(dir.join("deaths.csv"), "tick,agent_id\n1,1\n1,2\n").unwrap();
+        write(dir.join("agent_info.csv"), "tick,agent_id,cash,food,grain\n\
+                                           0,1,100,5,5\n\
+                                           0,2,80,5,5\n").unwrap();
+
+        let s = Summary::load(&dir).unwrap();
+        assert_eq!((s.ticks, s.deaths, s.survivors, s.mean_cash), (3, 2, 0, 0.));
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use failure::Error;
+use rand::prelude::SmallRng;
+use tracing::debug;
+
+use crate::agent::{AgentId, Agents, MU};
+use crate::goods::Good;
+use crate::ledger::Ledger;
+use crate::market::{ClearingMarket, Fill, Market, UnexecutedTrades};
+use crate::units::{Money, Quantity};
+
+/// Walrasian auctioneer. Before each round it asks every agent for its demand at candidate
+/// prices and bisects to the price where excess demand is zero, then trades at that price.
+/// Prices only move through this search, there is no adjustment between rounds.
+pub struct TatonnementMarket {
+    /// executes the trades at the price found, rationing whatever excess is left
+    inner: ClearingMarket,
+    /// most demand queries per good and round
+    pub max_iters: u32,
+    /// stop once the bracket around the clearing price is this narrow
+    pub tolerance: Money,
+}
+
+impl TatonnementMarket {
+    pub fn new(prices: HashMap<Good, Money>, rng: SmallRng, max_iters: u32, tolerance: Money) -> TatonnementMarket {
+        TatonnementMarket { inner: ClearingMarket::new(prices, rng), max_iters, tolerance: tolerance.max(Money(1)) }
+    }
+
+    /// Bisect for the price where `excess` demand changes sign, returns it with its excess.
+    /// `excess` must be non-increasing in price and non-negative at 0.
+    pub fn clearing_price(&self, high: Money, excess: impl Fn(Money) -> Quantity) -> (Money, Quantity) {
+        let (mut lo, mut hi) = (Money::ZERO, high.max(Money(1)));
+        let (mut z_lo, mut z_hi) = (excess(lo), excess(hi));
+        let mut iters = 2;
+        let cleared = |z: Quantity| z == Quantity::ZERO;
+        while hi - lo > self.tolerance && iters < self.max_iters && !cleared(z_lo) && !cleared(z_hi) {
+            let mid = Money(lo.0 + (hi.0 - lo.0) / 2);
+            let z = excess(mid);
+            iters += 1;
+            if z > Quantity::ZERO {
+                lo = mid;
+                z_lo = z;
+            } else {
+                hi = mid;
+                z_hi = z;
+            }
+        }
+        debug!(iters, %lo, %z_lo, %hi, %z_hi, "tatonnement");
+        if z_lo.abs() <= z_hi.abs() {
+            (lo, z_lo)
+        } else {
+            (hi, z_hi)
+        }
+    }
+}
+
+impl Market for TatonnementMarket {
+    fn goods(&self) -> Vec<Good> {
+        self.inner.goods()
+    }
+
+    fn price(&self, good: Good) -> Money {
+        self.inner.price(good)
+    }
+
+    fn old_price(&self, good: Good) -> Money {
+        self.inner.old_price(good)
+    }
+
+    fn trade(&mut self, cash_and_id: (Money, AgentId), good: Good, amt: Quantity) -> Result<(), Error> {
+        self.inner.trade(cash_and_id, good, amt)
+    }
+
+    fn collect_orders(&mut self, agents: &Agents, good: Good, mu: &MU) {
+        // nobody buys above the highest marginal utility
+        let high = agents.values()
+            .flat_map(|a| a.values(mu, good).0.iter().map(|&(u, _)| u).max())
+            .max().unwrap_or(Money::ZERO).max(self.price(good))
+            .saturating_add(Money(1));
+        let (price, _) = self.clearing_price(high, |p| {
+            agents.values().map(|a| a.demand(p, mu, good)).sum()
+        });
+        self.inner.set_price(good, price);
+        self.inner.collect_orders(agents, good, mu);
+    }
+
+    fn execute_trade(&mut self, agents: &mut Agents, good: Good, ledger: &mut Ledger) -> UnexecutedTrades {
+        self.inner.execute_trade(agents, good, ledger)
+    }
+
+    fn buy(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity,
+           ledger: &mut Ledger) -> Result<Fill, Error> {
+        self.inner.buy(agents, id, good, amt, ledger)
+    }
+
+    fn sell(&mut self, agents: &mut Agents, id: AgentId, good: Good, amt: Quantity,
+            ledger: &mut Ledger) -> Result<Fill, Error> {
+        self.inner.sell(agents, id, good, amt, ledger)
+    }
+
+    fn update_price(&mut self, _ts: UnexecutedTrades, good: Good) -> Money {
+        self.price(good)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use rand::SeedableRng;
+
+    use crate::agent::Agent;
+    use crate::testing::goods;
+
+    use super::*;
+
+    #[test]
+    fn finds_clearing_price() {
+        let (_, [food]) = goods(["Food"]);
+        let utility: Vec<_> = [0, 20, 35, 47, 57, 62].iter().map(|&u| Money(u)).collect();
+        let mu = MU::from_utility(&utility, 0.8);
+        let mut agents = Agents::new();
+        for &stock in &[0, 2, 12, 20] {
+            Agent::new_into_map(&mut agents, Money(500), hashmap! {food => Quantity(stock)}, hashmap! {food => 1.0}).unwrap();
+        }
+        let market = TatonnementMarket::new(hashmap! {food => Money(30)}, SmallRng::seed_from_u64(0), 30, Money(1));
+        let excess = |p| agents.values().map(|a| a.demand(p, &mu, food)).sum::<Quantity>();
+
+        let (p, z) = market.clearing_price(Money(40), excess);
+
+        assert_eq!(z, excess(p));
+        // no other price clears better
+        for q in 0..40 {
+            assert!(excess(Money(q)).abs() >= z.abs(), "{} clears better than {}", q, p);
+        }
+    }
+
+    #[test]
+    fn iteration_cap() {
+        let excess = |p: Money| Quantity(100 - p.0 as i32);
+        let market = TatonnementMarket::new(HashMap::new(), SmallRng::seed_from_u64(0), 30, Money(1));
+        assert_eq!(market.clearing_price(Money(1000), excess), (Money(100), Quantity(0)));
+
+        // one bisection step after evaluating both ends, 0 is closer to clearing than 500
+        let market = TatonnementMarket::new(HashMap::new(), SmallRng::seed_from_u64(0), 3, Money(1));
+        assert_eq!(market.clearing_price(Money(1000), excess), (Money(0), Quantity(100)));
+    }
+}
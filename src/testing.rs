@@ -0,0 +1,22 @@
+//! Fixtures shared by the unit tests
+
+use std::collections::HashMap;
+
+use rand::SeedableRng;
+use rand::prelude::SmallRng;
+
+use crate::goods::{Good, Goods};
+use crate::market::ClearingMarket;
+use crate::units::Money;
+
+/// Registry of `names`, and the good of each in the same order
+pub fn goods<const N: usize>(names: [&str; N]) -> (Goods, [Good; N]) {
+    let goods = Goods::from_names(&names).unwrap();
+    let all = names.map(|name| goods.get(name).unwrap());
+    (goods, all)
+}
+
+/// Clearing market starting at `prices`, with a fixed seed
+pub fn market(prices: HashMap<Good, Money>) -> ClearingMarket {
+    ClearingMarket::new(prices, SmallRng::seed_from_u64(0))
+}
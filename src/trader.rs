@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::agent::{Agent, MU};
+use crate::brain::Brain;
+use crate::goods::{Good, Task};
+use crate::market::Market;
+use crate::region::{Region, RegionId, Route};
+use crate::units::{Money, Quantity};
+
+/// Arbitrageur carrying goods between regions. Neither eats nor produces. At home with
+/// nothing to sell it buys the good whose price elsewhere most exceeds the local price plus
+/// transport and travels there with it. Arrived, it asks what the cargo cost and marks unsold
+/// cargo down every tick, below cost if need be, until it sells.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Trader {
+    /// most units carried per trip
+    pub capacity: Quantity,
+    /// least profit per unit, after transport, worth a trip
+    pub min_margin: Money,
+    /// fraction the asking price of unsold cargo drops each tick
+    pub markdown: f64,
+    /// asking price per unit of the cargo, starts at what it cost including transport
+    pub asking: HashMap<Good, Money>,
+}
+
+impl Trader {
+    pub fn new(capacity: Quantity, min_margin: Money, markdown: f64) -> Trader {
+        Trader { capacity, min_margin, markdown, asking: HashMap::new() }
+    }
+
+    /// Units sold (negative) at a posted `price`: all of the cargo, if the price is right.
+    /// Never buys in a trade round.
+    pub fn demand(&self, good: Good, price: Money, stock: Quantity) -> Quantity {
+        match self.asking.get(&good) {
+            Some(&ask) if price >= ask => -stock.max(Quantity::ZERO),
+            _ => Quantity::ZERO,
+        }
+    }
+
+    /// Every unit of cargo asked at the asking price, no bids
+    pub fn reservation_prices(&self, good: Good, stock: Quantity) -> (Vec<Money>, Vec<Money>) {
+        match self.asking.get(&good) {
+            Some(&ask) => (Vec::new(), vec![ask.max(Money(1)); stock.0.max(0) as usize]),
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Whether any cargo is left unsold
+    pub fn has_cargo(&self, res: &HashMap<Good, Quantity>) -> bool {
+        self.asking.keys().any(|g| res[g] > Quantity::ZERO)
+    }
+
+    /// Lower the asking price of unsold cargo
+    pub fn mark_down(&mut self) {
+        for ask in self.asking.values_mut() {
+            *ask = ask.nudge(-self.markdown).max(Money::ZERO);
+        }
+    }
+
+    /// Most profitable trip from `home` with `cash` to spend: the route, the good and how many
+    /// units to buy, none if no trip clears `min_margin` per unit
+    pub fn plan(&self, home: RegionId, cash: Money, routes: &[Route], regions: &[Region],
+                goods: &[Good]) -> Option<(Route, Good, Quantity)> {
+        let here = &regions[home as usize].market;
+        let mut best: Option<(Route, Good, Money, Money)> = None;
+        for route in routes.iter().filter(|r| r.from == home) {
+            let there = &regions[route.to as usize].market;
+            for &good in goods {
+                let unit = here.price(good).saturating_add(route.cost);
+                let margin = there.price(good).saturating_sub(unit);
+                if margin > self.min_margin && best.iter().all(|&(_, _, m, _)| margin > m) {
+                    best = Some((*route, good, margin, unit));
+                }
+            }
+        }
+        let (route, good, _, unit) = best?;
+        let qty = (cash.0 / unit.0.max(1)).min(self.capacity.0 as i64);
+        if qty > 0 {
+            Some((route, good, Quantity(qty as i32)))
+        } else {
+            None
+        }
+    }
+
+    /// Set the asking price of `good` to what it cost per unit
+    pub fn bought(&mut self, good: Good, qty: Quantity, cost: Money) {
+        if qty > Quantity::ZERO {
+            self.asking.insert(good, Money(cost.0 / qty.0 as i64));
+        }
+    }
+}
+
+impl Brain for Trader {
+    fn demand(&self, a: &Agent, price: Money, _mu: &MU, good: Good) -> Quantity {
+        Trader::demand(self, good, price, a.res[&good])
+    }
+
+    fn reservation_prices(&self, a: &Agent, _mu: &MU, good: Good) -> (Vec<Money>, Vec<Money>) {
+        Trader::reservation_prices(self, good, a.res[&good])
+    }
+
+    fn choose_task<'a>(&self, _a: &Agent, _tasks: &'a [Task], _market: &dyn Market) -> Option<&'a Task> {
+        None
+    }
+
+    fn consume(&self, _a: &Agent, _food: Good, _mu: &MU, _max: Quantity) -> Option<Quantity> {
+        None
+    }
+
+    fn employs(&self, _task: &Task) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::testing::{goods, market};
+
+    use super::*;
+
+    #[test]
+    fn plans_best_trip() {
+        let (goods, [food, grain]) = goods(["Food", "Grain"]);
+        let region = |name: &str, food_price, grain_price| Region {
+            name: name.into(),
+            market: Box::new(market(hashmap! {food => Money(food_price), grain => Money(grain_price)})),
+        };
+        let regions = vec![region("Farm", 20, 5), region("Town", 30, 20), region("Port", 40, 6)];
+        let routes = [Route { from: 0, to: 1, cost: Money(4), delay: 1 },
+                      Route { from: 0, to: 2, cost: Money(12), delay: 1 },
+                      Route { from: 1, to: 0, cost: Money(4), delay: 1 }];
+        let mut t = Trader::new(Quantity(10), Money(2), 0.5);
+
+        // grain to town makes 11 a unit, food to port 8
+        assert_eq!(t.plan(0, Money(1000), &routes, &regions, goods.all()), Some((routes[0], grain, Quantity(10))));
+        assert_eq!(t.plan(0, Money(50), &routes, &regions, goods.all()), Some((routes[0], grain, Quantity(5))));
+        // nothing is cheaper in the farm than in town
+        assert_eq!(t.plan(1, Money(1000), &routes, &regions, goods.all()), None);
+
+        t.bought(grain, Quantity(10), Money(90));
+        assert_eq!(t.demand(grain, Money(8), Quantity(10)), Quantity(0));
+        t.mark_down();
+        assert_eq!(t.demand(grain, Money(8), Quantity(10)), Quantity(-10));
+        assert_eq!(t.demand(food, Money(80), Quantity(10)), Quantity(0));
+
+        // cargo that won't sell is marked down to nothing, but never asked for less than 1
+        for _ in 0..5 {
+            t.mark_down();
+        }
+        assert_eq!(t.asking[&grain], Money::ZERO);
+        assert_eq!(t.reservation_prices(grain, Quantity(2)), (vec![], vec![Money(1); 2]));
+    }
+}
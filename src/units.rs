@@ -0,0 +1,192 @@
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+use failure::Error;
+
+/// An amount of cash. Prices, values and marginal utilities are all `Money`.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Money(pub i64);
+
+/// A number of units of some good
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Quantity(pub i32);
+
+/// Arithmetic shared by both units. The `checked_` methods report overflow as an error,
+/// the operators panic on it rather than wrap.
+macro_rules! unit {
+    ($t:ident, $inner:ty, $what:expr) => {
+        impl $t {
+            pub const ZERO: $t = $t(0);
+            pub const MAX: $t = $t(<$inner>::MAX);
+            pub const MIN: $t = $t(<$inner>::MIN);
+
+            pub fn checked_add(self, other: $t) -> Result<$t, Error> {
+                match self.0.checked_add(other.0) {
+                    Some(x) => Ok($t(x)),
+                    None => bail!("{} overflow: {} + {}", $what, self, other),
+                }
+            }
+
+            pub fn checked_sub(self, other: $t) -> Result<$t, Error> {
+                match self.0.checked_sub(other.0) {
+                    Some(x) => Ok($t(x)),
+                    None => bail!("{} overflow: {} - {}", $what, self, other),
+                }
+            }
+
+            pub fn saturating_add(self, other: $t) -> $t {
+                $t(self.0.saturating_add(other.0))
+            }
+
+            pub fn saturating_sub(self, other: $t) -> $t {
+                $t(self.0.saturating_sub(other.0))
+            }
+
+            pub fn abs(self) -> $t {
+                $t(self.0.saturating_abs())
+            }
+
+            /// Multiply by a factor and round, saturating at the bounds
+            pub fn scale(self, f: f64) -> $t {
+                // float to int casts saturate
+                $t((self.0 as f64 * f).round() as $inner)
+            }
+        }
+
+        impl Add for $t {
+            type Output = $t;
+            fn add(self, other: $t) -> $t {
+                self.checked_add(other).unwrap_or_else(|e| panic!("{}", e))
+            }
+        }
+
+        impl Sub for $t {
+            type Output = $t;
+            fn sub(self, other: $t) -> $t {
+                self.checked_sub(other).unwrap_or_else(|e| panic!("{}", e))
+            }
+        }
+
+        impl AddAssign for $t {
+            fn add_assign(&mut self, other: $t) {
+                *self = *self + other;
+            }
+        }
+
+        impl SubAssign for $t {
+            fn sub_assign(&mut self, other: $t) {
+                *self = *self - other;
+            }
+        }
+
+        impl Neg for $t {
+            type Output = $t;
+            fn neg(self) -> $t {
+                $t::ZERO - self
+            }
+        }
+
+        impl Sum for $t {
+            fn sum<I: Iterator<Item = $t>>(iter: I) -> $t {
+                iter.fold($t::ZERO, Add::add)
+            }
+        }
+
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        impl fmt::Debug for $t {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+unit!(Money, i64, "money");
+unit!(Quantity, i32, "quantity");
+
+impl Money {
+    /// Value of `q` units at this price
+    pub fn checked_mul(self, q: Quantity) -> Result<Money, Error> {
+        match self.0.checked_mul(q.0 as i64) {
+            Some(x) => Ok(Money(x)),
+            None => bail!("money overflow: {} * {}", self, q),
+        }
+    }
+
+    pub fn saturating_mul(self, q: Quantity) -> Money {
+        Money(self.0.saturating_mul(q.0 as i64))
+    }
+
+    /// Scale by `1 + frac`, moving by at least 1 the way `frac`'s sign points (`-0.` lowers),
+    /// so that a small amount doesn't get stuck rounding back to itself
+    pub fn nudge(self, frac: f64) -> Money {
+        if frac.is_sign_negative() {
+            self.scale(1. + frac).min(self.saturating_sub(Money(1)))
+        } else {
+            self.scale(1. + frac).max(self.saturating_add(Money(1)))
+        }
+    }
+
+    /// Price per unit when `q` units are worth this much, rounded towards zero
+    pub fn checked_div(self, q: Quantity) -> Result<Money, Error> {
+        if q == Quantity::ZERO {
+            bail!("money divided by zero: {} / {}", self, q);
+        }
+        match self.0.checked_div(q.0 as i64) {
+            Some(x) => Ok(Money(x)),
+            None => bail!("money overflow: {} / {}", self, q),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_is_an_error() {
+        let price = Money(25);
+        assert_eq!(price.checked_mul(Quantity(40)).unwrap(), Money(1000));
+        // would wrap with i16 backing
+        assert_eq!(Money(500).checked_mul(Quantity(400)).unwrap(), Money(200_000));
+
+        assert!(Money::MAX.checked_mul(Quantity(2)).is_err());
+        assert!(Money::MAX.checked_add(Money(1)).is_err());
+        assert!(Quantity::MIN.checked_sub(Quantity(1)).is_err());
+        let e = Money(10).checked_div(Quantity(0)).unwrap_err().to_string();
+        assert!(e.contains("divided by zero"), "{}", e);
+        assert!(Money::MIN.checked_div(Quantity(-1)).unwrap_err().to_string().contains("overflow"));
+        assert_eq!(Money::MAX.saturating_mul(Quantity(2)), Money::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "quantity overflow")]
+    fn operators_panic() {
+        let _ = Quantity::MAX + Quantity(1);
+    }
+
+    #[test]
+    fn scale_rounds() {
+        assert_eq!(Quantity(10).scale(0.25), Quantity(3));
+        assert_eq!(Money(100).scale(1.125), Money(113));
+        assert_eq!(Money(1).scale(1e30), Money::MAX);
+    }
+
+    #[test]
+    fn nudge_moves_at_least_1() {
+        assert_eq!(Money(100).nudge(0.1), Money(110));
+        assert_eq!(Money(100).nudge(-0.1), Money(90));
+        assert_eq!(Money(4).nudge(0.1), Money(5));
+        assert_eq!(Money(4).nudge(-0.1), Money(3));
+        assert_eq!(Money(4).nudge(0.), Money(5));
+        assert_eq!(Money(4).nudge(-0.), Money(3));
+    }
+}
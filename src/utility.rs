@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::agent::MU;
+use crate::goods::{Good, GoodDef, Goods};
+use crate::units::{Money, Quantity};
+
+/// Utility, in money, of the basket of goods eaten in a tick
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Utility {
+    /// each good adds its own utility, the marginal utility of each successive unit eaten
+    Additive(BTreeMap<Good, Vec<Money>>),
+    /// `scale` × Π (1 + x)^weight, goods substitute for each other with elasticity 1
+    CobbDouglas { weights: BTreeMap<Good, f64>, scale: f64 },
+    /// `scale` × (Σ weight × (1 + x)^rho)^(1 / rho), goods substitute with elasticity
+    /// 1 / (1 - rho): almost perfectly as rho nears 1, they complement each other below 0
+    Ces { weights: BTreeMap<Good, f64>, rho: f64, scale: f64 },
+}
+
+impl Utility {
+    /// Goods eaten, in good order
+    pub fn goods(&self) -> Vec<Good> {
+        match self {
+            Utility::Additive(mu) => mu.keys().cloned().collect(),
+            Utility::CobbDouglas { weights, .. } | Utility::Ces { weights, .. } => weights.keys().cloned().collect(),
+        }
+    }
+
+    /// Utility of eating `basket`, nothing for eating nothing
+    pub fn of(&self, basket: &BTreeMap<Good, Quantity>) -> Money {
+        match self {
+            Utility::Additive(mu) => mu.iter()
+                .flat_map(|(g, mu)| mu.iter().take(eaten(basket, *g) as usize))
+                .fold(Money::ZERO, |total, &m| total.saturating_add(m)),
+            _ => Money((self.level(basket) - self.level(&BTreeMap::new())).round() as i64),
+        }
+    }
+
+    /// Utility of eating one more unit of `good` on top of `basket`
+    pub fn marginal(&self, basket: &BTreeMap<Good, Quantity>, good: Good) -> Money {
+        match self {
+            Utility::Additive(mu) => mu.get(&good)
+                .and_then(|mu| mu.get(eaten(basket, good) as usize))
+                .cloned()
+                .unwrap_or(Money::ZERO),
+            _ => {
+                let mut more = basket.clone();
+                *more.entry(good).or_insert(Quantity::ZERO) += Quantity(1);
+                Money((self.level(&more) - self.level(basket)).round() as i64)
+            }
+        }
+    }
+
+    fn level(&self, basket: &BTreeMap<Good, Quantity>) -> f64 {
+        let x = |g: &Good| 1. + eaten(basket, *g) as f64;
+        match self {
+            Utility::Additive(_) => self.of(basket).0 as f64,
+            Utility::CobbDouglas { weights, scale } =>
+                scale * weights.iter().map(|(g, w)| x(g).powf(*w)).product::<f64>(),
+            Utility::Ces { weights, rho, scale } =>
+                scale * weights.iter().map(|(g, w)| w * x(g).powf(*rho)).sum::<f64>().powf(1. / rho),
+        }
+    }
+}
+
+fn eaten(basket: &BTreeMap<Good, Quantity>, good: Good) -> i32 {
+    basket.get(&good).map_or(0, |q| q.0.max(0))
+}
+
+/// What an agent eats and how much it enjoys it: `utility` of what it eats in a tick,
+/// `discount` on a unit kept to eat in a later tick, and at most `max` units of each good
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Preferences {
+    pub utility: Utility,
+    pub discount: f64,
+    pub max: Quantity,
+    /// goods it eats that spoil or cost to store, it saves less of them
+    pub perishable: BTreeMap<Good, GoodDef>,
+}
+
+impl Default for Preferences {
+    /// Eats nothing
+    fn default() -> Self {
+        Preferences {
+            utility: Utility::Additive(BTreeMap::new()),
+            discount: 0.,
+            max: Quantity::ZERO,
+            perishable: BTreeMap::new(),
+        }
+    }
+}
+
+impl Preferences {
+    /// Takes how the goods it eats keep from `goods`, which must have all of them
+    pub fn with_goods(mut self, goods: &Goods) -> Preferences {
+        self.perishable = self.utility.goods().into_iter()
+            .map(|g| (g, goods.def(g).clone()))
+            .filter(|(_, def)| def.perishes())
+            .collect();
+        self
+    }
+
+    pub fn eats(&self, good: Good) -> bool {
+        self.utility.goods().contains(&good)
+    }
+
+    /// Marginal utility of each successive unit of `good` eaten now or kept for the next four
+    /// ticks, with the other goods it eats at what it `holds` of them, up to `max`.
+    /// Less for kept units the more they spoil and cost to store.
+    pub fn mu(&self, good: Good, holds: &HashMap<Good, Quantity>) -> MU {
+        let curr = match &self.utility {
+            Utility::Additive(mu) => mu.get(&good).cloned().unwrap_or_default(),
+            u => {
+                let mut basket: BTreeMap<_, _> = u.goods().into_iter()
+                    .map(|g| (g, holds.get(&g).cloned().unwrap_or(Quantity::ZERO).min(self.max)))
+                    .collect();
+                (0..self.max.0)
+                    .map(|n| {
+                        basket.insert(good, Quantity(n));
+                        u.marginal(&basket, good)
+                    })
+                    .collect()
+            }
+        };
+        let mu = MU::from_curr_mu(&curr, self.discount);
+        match self.perishable.get(&good) {
+            Some(def) => mu.kept(def.decay, def.storage),
+            None => mu,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::{btreemap, hashmap};
+
+    use crate::testing::goods;
+
+    use super::*;
+
+    #[test]
+    fn substitutes() {
+        let (_, [bread, fish]) = goods(["Bread", "Fish"]);
+        let additive = Utility::Additive(btreemap! {bread => vec![Money(30), Money(20)], fish => vec![Money(25)]});
+        let cobb_douglas = Utility::CobbDouglas { weights: btreemap! {bread => 0.5, fish => 0.5}, scale: 100. };
+        let basket = btreemap! {bread => Quantity(3)};
+
+        assert_eq!(additive.of(&basket), Money(50));
+        assert_eq!(additive.marginal(&basket, fish), Money(25));
+        // sqrt(4 × 1) - 1, a first fish is worth more than a fourth loaf
+        assert_eq!(cobb_douglas.of(&basket), Money(100));
+        assert_eq!(cobb_douglas.marginal(&basket, fish), Money(83));
+        assert_eq!(cobb_douglas.marginal(&basket, bread), Money(24));
+
+        // the more fish it holds, the more a loaf adds
+        let p = Preferences { utility: cobb_douglas, discount: 0.5, max: Quantity(5), perishable: BTreeMap::new() };
+        let few = p.mu(bread, &hashmap! {bread => Quantity(0), fish => Quantity(0)});
+        let many = p.mu(bread, &hashmap! {bread => Quantity(0), fish => Quantity(5)});
+        assert_eq!(few.0[0], (Money(41), 0));
+        assert_eq!(many.0[0], (Money(101), 0));
+        assert!(!Preferences::default().eats(bread));
+    }
+}